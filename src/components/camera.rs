@@ -0,0 +1,54 @@
+use bevy::prelude::*;
+
+/// How the camera follows the player
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum CameraMode {
+    /// At the player's eyes
+    FirstPerson,
+    /// Behind the player, looking the same way they are
+    ThirdPerson,
+    /// Circling around a point, for looking at builds. Looking around moves the camera instead of the player.
+    Orbit
+}
+
+impl CameraMode {
+    /// The mode after this one, when switching between them
+    pub(crate) fn next(self) -> Self {
+        match self {
+            Self::FirstPerson => Self::ThirdPerson,
+            Self::ThirdPerson => Self::Orbit,
+            Self::Orbit => Self::FirstPerson
+        }
+    }
+}
+
+/// Where the camera is in orbit mode
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct Orbit {
+    /// Point that the camera circles around and looks at
+    pub(crate) focus: Vec3,
+    /// Pitch and yaw of the camera, in radians
+    pub(crate) angles: (f32, f32),
+    /// How far the camera is from the focus, in world units
+    pub(crate) distance: f32
+}
+
+/// The camera that shows the world from the player's point of view. It's a separate entity from the player, and
+/// follows them around.
+#[derive(Component, Clone, Debug)]
+pub(crate) struct PlayerCamera {
+    pub(crate) mode: CameraMode,
+    pub(crate) orbit: Orbit,
+    /// Where the camera was when the mode last changed, and how far along moving from there it is (from 0 to 1)
+    pub(crate) transition: Option<(Transform, f32)>
+}
+
+impl Default for PlayerCamera {
+    fn default() -> Self {
+        Self {
+            mode: CameraMode::FirstPerson,
+            orbit: Orbit { focus: Vec3::ZERO, angles: (0.0, 0.0), distance: 0.0 },
+            transition: None
+        }
+    }
+}
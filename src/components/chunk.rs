@@ -0,0 +1,6 @@
+use bevy::prelude::*;
+use crate::world::coords::ChunkPos;
+
+/// Entity that renders the chunk at this position
+#[derive(Component, Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ChunkEntity(pub(crate) ChunkPos);
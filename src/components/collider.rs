@@ -0,0 +1,25 @@
+use bevy::prelude::*;
+
+use crate::world::coords::WorldPos;
+
+/// Axis aligned box that an entity takes up, relative to its translation
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct Collider {
+    pub(crate) min: Vec3,
+    pub(crate) max: Vec3
+}
+
+impl Collider {
+    /// Corners of the box when the entity is at `translation`
+    pub(crate) fn bounds(&self, translation: Vec3) -> (Vec3, Vec3) {
+        (translation + self.min, translation + self.max)
+    }
+
+    /// Whether the box overlaps the voxel at `pos` when the entity is at `translation`. Only touching doesn't count.
+    pub(crate) fn overlaps_voxel(&self, translation: Vec3, pos: WorldPos) -> bool {
+        let (min, max) = self.bounds(translation);
+        let (voxel_min, voxel_max) = (pos.corner().0, (pos + IVec3::ONE).corner().0);
+
+        min.cmplt(voxel_max).all() && max.cmpgt(voxel_min).all()
+    }
+}
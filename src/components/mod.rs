@@ -1,3 +1,9 @@
 mod player;
+mod chunk;
+mod collider;
+mod camera;
 
-pub(crate) use player::*;
\ No newline at end of file
+pub(crate) use player::*;
+pub(crate) use chunk::*;
+pub(crate) use collider::*;
+pub(crate) use camera::*;
\ No newline at end of file
@@ -1,12 +1,152 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Component)]
+use crate::components::Collider;
+use crate::util::Axis;
+use crate::world::voxel::Voxel;
+
+/// How wide and tall the player is, in world units
+pub(crate) const PLAYER_WIDTH: f32 = 0.6;
+pub(crate) const PLAYER_HEIGHT: f32 = 1.8;
+/// How far above the player's feet the camera is
+pub(crate) const EYE_HEIGHT: f32 = 1.6;
+
+/// Voxels the player can place, one for each way they can be aligned
+pub(crate) const HOTBAR: [Voxel; 3] = [
+    Voxel::active().with_axis(Axis::X),
+    Voxel::active().with_axis(Axis::Y),
+    Voxel::active().with_axis(Axis::Z)
+];
+
+/// Pitch and yaw, in radians
+pub(crate) type Rotation = (f32, f32);
+
+/// Rotation that looks in the direction given by `rot`
+pub(crate) fn look_rotation(rot: Rotation) -> Quat {
+    Quat::from_axis_angle(Vec3::Y, rot.1) * Quat::from_axis_angle(-Vec3::X, rot.0)
+}
+
+/// Pitch and yaw that `rotation` is looking in, the opposite of `look_rotation`
+pub(crate) fn look_angles(rotation: Quat) -> Rotation {
+    let fwd = rotation * -Vec3::Z;
+    (-fwd.y.clamp(-1.0, 1.0).asin(), (-fwd.x).atan2(-fwd.z))
+}
+
+/// How the player moves around
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum MovementMode {
+    /// On the ground, with gravity
+    Walk,
+    /// Flying, but still colliding with voxels
+    Fly,
+    /// Flying through everything
+    Noclip
+}
+
+impl MovementMode {
+    /// The mode after this one, when switching between them
+    pub(crate) fn next(self) -> Self {
+        match self {
+            Self::Walk => Self::Fly,
+            Self::Fly => Self::Noclip,
+            Self::Noclip => Self::Walk
+        }
+    }
+
+    /// Whether gravity pulls the player down
+    pub(crate) fn has_gravity(self) -> bool {
+        self == Self::Walk
+    }
+
+    /// Whether the player bumps into voxels
+    pub(crate) fn collides(self) -> bool {
+        self != Self::Noclip
+    }
+}
+
+/// Everything about the player that matters for gameplay, which is saved with the world. The player doesn't have a
+/// transform, the camera works out where it should be from this instead.
+#[derive(Component, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub(crate) struct Player {
-    position: Vec3,
+    /// Where the player's feet are
+    pub(crate) position: Vec3,
+    /// In world units per second
+    pub(crate) velocity: Vec3,
+    /// Whether the player is standing on something
+    pub(crate) grounded: bool,
+    pub(crate) mode: MovementMode,
+    /// How far above their feet the player's eyes are
+    pub(crate) eye_height: f32,
+    /// Which voxel in `HOTBAR` the player places
+    pub(crate) slot: usize,
+    /// Which way the player is looking
+    pub(crate) look: Rotation
 }
 
 impl Default for Player {
     fn default() -> Self {
-        Self { position: Vec3::new(0.0, 0.0, 0.0) }
+        Self {
+            position: Vec3::ZERO,
+            velocity: Vec3::ZERO,
+            grounded: false,
+            mode: MovementMode::Walk,
+            eye_height: EYE_HEIGHT,
+            slot: 1,
+            look: (0.0, 0.0)
+        }
     }
-}
\ No newline at end of file
+}
+
+impl Player {
+    /// A player with their eyes at `eye`, looking in the direction given by `look`
+    pub(crate) fn with_eyes_at(eye: Vec3, look: Rotation) -> Self {
+        Self { position: eye - Vec3::Y * EYE_HEIGHT, look, ..Default::default() }
+    }
+
+    /// The player's collision box, around their feet
+    pub(crate) fn collider() -> Collider {
+        Collider {
+            min: Vec3::new(-PLAYER_WIDTH / 2.0, 0.0, -PLAYER_WIDTH / 2.0),
+            max: Vec3::new(PLAYER_WIDTH / 2.0, PLAYER_HEIGHT, PLAYER_WIDTH / 2.0)
+        }
+    }
+
+    pub(crate) fn eye_position(&self) -> Vec3 {
+        self.position + Vec3::Y * self.eye_height
+    }
+
+    /// Where the player's eyes are and which way they're looking, which the camera follows
+    pub(crate) fn eye(&self) -> Transform {
+        Transform::from_translation(self.eye_position()).with_rotation(look_rotation(self.look))
+    }
+
+    /// The voxel the player places
+    pub(crate) fn selected(&self) -> Voxel {
+        HOTBAR[self.slot % HOTBAR.len()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saving() {
+        // Everything survives being sent somewhere else and back
+        let player = Player {
+            position: Vec3::new(1.0, 2.0, 3.0),
+            velocity: Vec3::new(0.0, -4.0, 0.5),
+            mode: MovementMode::Fly,
+            slot: 2,
+            look: (0.3, -1.2),
+            ..Default::default()
+        };
+        let bytes = bincode::serialize(&player).unwrap();
+        assert_eq!(bincode::deserialize::<Player>(&bytes).unwrap(), player);
+
+        let eyes = Player::with_eyes_at(Vec3::new(0.0, 10.0, 0.0), (0.0, 0.0));
+        assert_eq!(eyes.eye_position(), Vec3::new(0.0, 10.0, 0.0));
+        assert_eq!(eyes.position.y, 10.0 - EYE_HEIGHT);
+    }
+}
@@ -0,0 +1,441 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use bevy::prelude::*;
+use serde::de::IntoDeserializer;
+use serde::{Deserialize, Serialize};
+
+use crate::settings::{SettingsError, StickSettings};
+
+/// Something the player can do, which can be bound to keys, mouse buttons and gamepad buttons.
+/// Gameplay systems look at `Input<Action>` instead of at the keys themselves.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) enum Action {
+    MoveForward,
+    MoveBack,
+    MoveLeft,
+    MoveRight,
+    /// Also goes up when flying
+    Jump,
+    /// Also goes down when flying
+    Crouch,
+    Sprint,
+    ToggleMovementMode,
+    /// Switch between first person, third person and orbiting
+    ToggleCamera,
+    Break,
+    Place,
+    /// Align placed voxels along the X axis
+    AlignX,
+    AlignY,
+    AlignZ,
+    /// Let go of the cursor and pause the game, or resume it
+    Pause
+}
+
+impl Action {
+    pub(crate) const ALL: [Self; 15] = [
+        Self::MoveForward, Self::MoveBack, Self::MoveLeft, Self::MoveRight,
+        Self::Jump, Self::Crouch, Self::Sprint, Self::ToggleMovementMode, Self::ToggleCamera,
+        Self::Break, Self::Place,
+        Self::AlignX, Self::AlignY, Self::AlignZ,
+        Self::Pause
+    ];
+
+    /// Name of the action in the config
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::MoveForward => "move_forward",
+            Self::MoveBack => "move_back",
+            Self::MoveLeft => "move_left",
+            Self::MoveRight => "move_right",
+            Self::Jump => "jump",
+            Self::Crouch => "crouch",
+            Self::Sprint => "sprint",
+            Self::ToggleMovementMode => "toggle_movement_mode",
+            Self::ToggleCamera => "toggle_camera",
+            Self::Break => "break",
+            Self::Place => "place",
+            Self::AlignX => "align_x",
+            Self::AlignY => "align_y",
+            Self::AlignZ => "align_z",
+            Self::Pause => "pause"
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|action| action.name() == name)
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A button that can trigger an action. In the config these are written as `key:W`, `mouse:Left` or
+/// `gamepad:South`, using the names of bevy's `KeyCode`, `MouseButton` and `GamepadButtonType`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    /// This button on any gamepad
+    Gamepad(GamepadButtonType)
+}
+
+impl Binding {
+    pub(crate) fn pressed(&self, keys: &Input<KeyCode>, mouse: &Input<MouseButton>, gamepad: &Input<GamepadButton>) -> bool {
+        match *self {
+            Self::Key(key) => keys.pressed(key),
+            Self::Mouse(button) => mouse.pressed(button),
+            Self::Gamepad(button) => gamepad.get_pressed().any(|pressed| pressed.1 == button)
+        }
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Key(key) => write!(f, "key:{:?}", key),
+            Self::Mouse(MouseButton::Other(button)) => write!(f, "mouse:{}", button),
+            Self::Mouse(button) => write!(f, "mouse:{:?}", button),
+            Self::Gamepad(button) => write!(f, "gamepad:{:?}", button)
+        }
+    }
+}
+
+/// Parse the name of a variant of one of bevy's input enums
+fn variant<'de, T: Deserialize<'de>>(name: &'de str) -> Option<T> {
+    T::deserialize(IntoDeserializer::<serde::de::value::Error>::into_deserializer(name)).ok()
+}
+
+impl FromStr for Binding {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (device, name) = text.split_once(':')
+            .ok_or_else(|| format!("'{}' should look like key:W, mouse:Left or gamepad:South", text))?;
+
+        let binding = match device {
+            "key" => variant(name).map(Self::Key),
+            // Extra mouse buttons are numbers
+            "mouse" => variant(name).or_else(|| name.parse().ok().map(MouseButton::Other)).map(Self::Mouse),
+            "gamepad" => variant(name).map(Self::Gamepad),
+            _ => return Err(format!("unknown device '{}' in '{}', should be key, mouse or gamepad", device, text))
+        };
+        binding.ok_or_else(|| format!("unknown {} button '{}'", device, name))
+    }
+}
+
+impl TryFrom<String> for Binding {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        text.parse()
+    }
+}
+
+impl From<Binding> for String {
+    fn from(binding: Binding) -> Self {
+        binding.to_string()
+    }
+}
+
+/// One of the analog sticks on a gamepad
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum Stick {
+    Left,
+    Right
+}
+
+impl Stick {
+    fn axes(self) -> (GamepadAxisType, GamepadAxisType) {
+        match self {
+            Self::Left => (GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY),
+            Self::Right => (GamepadAxisType::RightStickX, GamepadAxisType::RightStickY)
+        }
+    }
+
+    /// Where this stick is on whichever connected gamepad has it pushed the furthest, with `settings` applied.
+    /// Right and up are positive.
+    pub(crate) fn read(self, gamepads: &Gamepads, axes: &Axis<GamepadAxis>, settings: &StickSettings) -> Vec2 {
+        let (x, y) = self.axes();
+        let raw = gamepads.iter()
+            .map(|&gamepad| Vec2::new(
+                axes.get(GamepadAxis(gamepad, x)).unwrap_or(0.0),
+                axes.get(GamepadAxis(gamepad, y)).unwrap_or(0.0)
+            ))
+            .fold(Vec2::ZERO, |furthest, position| if position.length() > furthest.length() { position } else { furthest });
+
+        settings.apply(raw)
+    }
+}
+
+/// The same binding is used for two different actions
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct BindingConflict {
+    pub(crate) binding: Binding,
+    pub(crate) actions: (Action, Action)
+}
+
+impl fmt::Display for BindingConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is bound to both {} and {}", self.binding, self.actions.0, self.actions.1)
+    }
+}
+
+/// What every action is bound to, the `[bindings]` section of the config. Actions can have any number of bindings,
+/// actions that aren't in the config keep their default bindings.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Bindings {
+    move_forward: Vec<Binding>,
+    move_back: Vec<Binding>,
+    move_left: Vec<Binding>,
+    move_right: Vec<Binding>,
+    jump: Vec<Binding>,
+    crouch: Vec<Binding>,
+    sprint: Vec<Binding>,
+    toggle_movement_mode: Vec<Binding>,
+    toggle_camera: Vec<Binding>,
+    r#break: Vec<Binding>,
+    place: Vec<Binding>,
+    align_x: Vec<Binding>,
+    align_y: Vec<Binding>,
+    align_z: Vec<Binding>,
+    pause: Vec<Binding>
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        use Binding::*;
+
+        // Moving around with a gamepad is done with the left stick, see `Stick`
+        Self {
+            move_forward: vec![Key(KeyCode::W)],
+            move_back: vec![Key(KeyCode::S)],
+            move_left: vec![Key(KeyCode::A)],
+            move_right: vec![Key(KeyCode::D)],
+            jump: vec![Key(KeyCode::Space), Gamepad(GamepadButtonType::South)],
+            crouch: vec![Key(KeyCode::LShift), Gamepad(GamepadButtonType::East)],
+            sprint: vec![Key(KeyCode::LControl), Gamepad(GamepadButtonType::LeftThumb)],
+            toggle_movement_mode: vec![Key(KeyCode::V), Gamepad(GamepadButtonType::North)],
+            toggle_camera: vec![Key(KeyCode::F5), Gamepad(GamepadButtonType::Select)],
+            r#break: vec![Mouse(MouseButton::Left), Gamepad(GamepadButtonType::RightTrigger2)],
+            place: vec![Mouse(MouseButton::Right), Gamepad(GamepadButtonType::LeftTrigger2)],
+            align_x: vec![Key(KeyCode::Key1), Gamepad(GamepadButtonType::LeftTrigger)],
+            align_y: vec![Key(KeyCode::Key2), Gamepad(GamepadButtonType::West)],
+            align_z: vec![Key(KeyCode::Key3), Gamepad(GamepadButtonType::RightTrigger)],
+            pause: vec![Key(KeyCode::Escape), Gamepad(GamepadButtonType::Start)]
+        }
+    }
+}
+
+impl Bindings {
+    pub(crate) fn get(&self, action: Action) -> &[Binding] {
+        match action {
+            Action::MoveForward => &self.move_forward,
+            Action::MoveBack => &self.move_back,
+            Action::MoveLeft => &self.move_left,
+            Action::MoveRight => &self.move_right,
+            Action::Jump => &self.jump,
+            Action::Crouch => &self.crouch,
+            Action::Sprint => &self.sprint,
+            Action::ToggleMovementMode => &self.toggle_movement_mode,
+            Action::ToggleCamera => &self.toggle_camera,
+            Action::Break => &self.r#break,
+            Action::Place => &self.place,
+            Action::AlignX => &self.align_x,
+            Action::AlignY => &self.align_y,
+            Action::AlignZ => &self.align_z,
+            Action::Pause => &self.pause
+        }
+    }
+
+    fn get_mut(&mut self, action: Action) -> &mut Vec<Binding> {
+        match action {
+            Action::MoveForward => &mut self.move_forward,
+            Action::MoveBack => &mut self.move_back,
+            Action::MoveLeft => &mut self.move_left,
+            Action::MoveRight => &mut self.move_right,
+            Action::Jump => &mut self.jump,
+            Action::Crouch => &mut self.crouch,
+            Action::Sprint => &mut self.sprint,
+            Action::ToggleMovementMode => &mut self.toggle_movement_mode,
+            Action::ToggleCamera => &mut self.toggle_camera,
+            Action::Break => &mut self.r#break,
+            Action::Place => &mut self.place,
+            Action::AlignX => &mut self.align_x,
+            Action::AlignY => &mut self.align_y,
+            Action::AlignZ => &mut self.align_z,
+            Action::Pause => &mut self.pause
+        }
+    }
+
+    /// Action that `binding` is bound to, other than `except`
+    fn bound_to(&self, binding: Binding, except: Action) -> Option<Action> {
+        Action::ALL.iter().copied().find(|&action| action != except && self.get(action).contains(&binding))
+    }
+
+    /// Every binding that's used for more than one action
+    pub(crate) fn conflicts(&self) -> Vec<BindingConflict> {
+        let mut conflicts = Vec::new();
+        for (i, &action) in Action::ALL.iter().enumerate() {
+            for &binding in self.get(action) {
+                if let Some(&other) = Action::ALL[i + 1..].iter().find(|other| self.get(**other).contains(&binding)) {
+                    conflicts.push(BindingConflict { binding, actions: (action, other) });
+                }
+            }
+        }
+        conflicts
+    }
+
+    /// Bind `action` to `bindings` instead of what it was bound to before. Nothing changes if one of them is already
+    /// used for another action.
+    pub(crate) fn bind(&mut self, action: Action, bindings: Vec<Binding>) -> Result<(), BindingConflict> {
+        for &binding in &bindings {
+            if let Some(other) = self.bound_to(binding, action) {
+                return Err(BindingConflict { binding, actions: (action, other) });
+            }
+        }
+
+        *self.get_mut(action) = bindings;
+        Ok(())
+    }
+
+    /// Write these bindings into the `[bindings]` section of the config file at `path`. Only the actions that are
+    /// bound differently than the file says are changed, everything else in the file (comments too) is kept as it is.
+    ///
+    /// The file is written to a temporary file first which then replaces the config, so if we crash halfway through
+    /// the old config is still intact.
+    pub(crate) fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SettingsError> {
+        let path = path.as_ref();
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(error.into())
+        };
+
+        let mut config: toml_edit::Document = text.parse()?;
+        let table = config.entry("bindings").or_insert(toml_edit::table());
+        if !table.is_table() {
+            *table = toml_edit::table();
+        }
+
+        let defaults = Self::default();
+        for action in Action::ALL {
+            let bindings: Vec<String> = self.get(action).iter().map(|binding| binding.to_string()).collect();
+            let old = &mut table[action.name()];
+            let same = match old.as_array() {
+                Some(array) => array.iter().map(|value| value.as_str()).eq(bindings.iter().map(|binding| Some(binding.as_str()))),
+                None => old.is_none() && defaults.get(action) == self.get(action)
+            };
+            if same {
+                continue;
+            }
+
+            // Keep any comment after the old value
+            let mut new = toml_edit::Value::from_iter(bindings);
+            if let Some(value) = old.as_value() {
+                *new.decor_mut() = value.decor().clone();
+            }
+            *old = toml_edit::Item::Value(new);
+        }
+
+        let tmp_path = path.with_extension("toml.tmp");
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(config.to_string().as_bytes())?;
+        file.sync_all()?;
+        drop(file);
+
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::util::testing::test_file;
+
+    #[test]
+    fn parsing_bindings() {
+        for binding in [
+            Binding::Key(KeyCode::Space),
+            Binding::Mouse(MouseButton::Left),
+            Binding::Mouse(MouseButton::Other(4)),
+            Binding::Gamepad(GamepadButtonType::RightTrigger2)
+        ] {
+            assert_eq!(binding.to_string().parse::<Binding>(), Ok(binding));
+        }
+        assert_eq!("key:W".parse::<Binding>(), Ok(Binding::Key(KeyCode::W)));
+
+        assert!("W".parse::<Binding>().is_err());
+        assert!("keyboard:W".parse::<Binding>().is_err());
+        assert_eq!("key:Spacebar".parse::<Binding>(), Err("unknown key button 'Spacebar'".to_owned()));
+    }
+
+    #[test]
+    fn config() {
+        let (settings, warnings) = Settings::parse("[bindings]\njump = [\"key:J\", \"gamepad:South\"]\nbreak = []\ndance = [\"key:Z\"]").unwrap();
+        let bindings = &settings.bindings;
+        assert_eq!(bindings.get(Action::Jump), &[Binding::Key(KeyCode::J), Binding::Gamepad(GamepadButtonType::South)]);
+        assert!(bindings.get(Action::Break).is_empty());
+        // Left out, so it's still bound to the default
+        assert_eq!(bindings.get(Action::MoveForward), Bindings::default().get(Action::MoveForward));
+        assert_eq!(warnings, vec!["unknown setting 'bindings.dance'"]);
+
+        // Bad bindings say which action they're for
+        let error = Settings::parse("[bindings]\n\njump = [\"key:Spacebar\"]").unwrap_err();
+        assert!(error.to_string().contains("bindings.jump"), "{}", error);
+    }
+
+    #[test]
+    fn conflicts() {
+        assert!(Bindings::default().conflicts().is_empty());
+
+        let error = Settings::parse("[bindings]\njump = [\"key:W\"]").unwrap_err();
+        assert_eq!(error.to_string(), "invalid value for bindings: key:W is bound to both move_forward and jump");
+
+        let mut bindings = Bindings::default();
+        let conflict = bindings.bind(Action::Place, vec![Binding::Key(KeyCode::E), Binding::Key(KeyCode::V)]).unwrap_err();
+        assert_eq!(conflict.actions, (Action::Place, Action::ToggleMovementMode));
+        assert_eq!(bindings, Bindings::default());
+
+        // Rebinding an action to something it's already bound to is fine
+        bindings.bind(Action::Place, vec![Binding::Mouse(MouseButton::Right), Binding::Key(KeyCode::E)]).unwrap();
+        assert_eq!(bindings.get(Action::Place).len(), 2);
+    }
+
+    #[test]
+    fn saving() {
+        let path = test_file("saving-bindings", "toml");
+        let config = "# Comments are kept\n[controls]\nmouse_sens = 0.2\n\n[bindings]\n# Sprinting\nsprint = [\"key:Q\"] # not shift\njump = [\"key:Space\"] # jumping\n";
+        fs::write(&path, config).unwrap();
+
+        let (mut settings, _) = Settings::load(&path).unwrap();
+        settings.bindings.bind(Action::Jump, vec![Binding::Gamepad(GamepadButtonType::South)]).unwrap();
+        settings.bindings.save(&path).unwrap();
+
+        // Everything else in the file is still there
+        let (loaded, warnings) = Settings::load(&path).unwrap();
+        assert_eq!(loaded, settings);
+        assert_eq!(loaded.controls.mouse_sens, 0.2);
+        assert_eq!(loaded.bindings.get(Action::Sprint), &[Binding::Key(KeyCode::Q)]);
+        assert!(warnings.is_empty());
+
+        // Only the binding that changed is different, and every comment is still there
+        let saved = fs::read_to_string(&path).unwrap();
+        assert_eq!(saved, config.replace("jump = [\"key:Space\"]", "jump = [\"gamepad:South\"]"));
+        assert!(!path.with_extension("toml.tmp").exists());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -6,48 +6,171 @@ mod components;
 
 mod world;
 mod util;
+mod settings;
+mod input;
 
+use bevy::input::InputSystem;
 use bevy::prelude::*;
+use crate::world::chunk::set_voxel_scale;
 use crate::world::manager::ChunkManager;
+use crate::world::region::WorldStorage;
+use crate::world::meta::WorldMeta;
+use crate::world::backup::{backup_world, BACKUPS_TO_KEEP};
+use crate::world::queue::LoadQueue;
+use crate::settings::Settings;
+use crate::input::{Action, Binding};
+use crate::world::definitions::VoxelDefinitions;
+
+const WORLD_DIR: &str = "worlds/default";
+const BACKUP_DIR: &str = "worlds/backups";
+const CONFIG_FILE: &str = "resources/config.toml";
+const VOXELS_FILE: &str = "resources/voxels.toml";
 
 fn main() {
+    // `svep backup` makes a backup of the world instead of starting the game
+    if std::env::args().nth(1).as_deref() == Some("backup") {
+        match backup_world(WORLD_DIR, BACKUP_DIR, BACKUPS_TO_KEEP) {
+            Ok(path) => println!("backed up '{}' to '{}'", WORLD_DIR, path.display()),
+            Err(error) => {
+                eprintln!("couldn't back up world '{}': {}", WORLD_DIR, error);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // `svep bind <action> <bindings...>` rebinds an action in the config
+    if std::env::args().nth(1).as_deref() == Some("bind") {
+        bind(std::env::args().skip(2).collect());
+        return;
+    }
+
+    let settings = match Settings::load(CONFIG_FILE) {
+        Ok((settings, warnings)) => {
+            for warning in warnings {
+                eprintln!("warning: {} in '{}'", warning, CONFIG_FILE);
+            }
+            settings
+        },
+        Err(error) => {
+            eprintln!("couldn't load settings from '{}': {}", CONFIG_FILE, error);
+            std::process::exit(1);
+        }
+    };
+
+    let definitions = match VoxelDefinitions::load(VOXELS_FILE) {
+        Ok(definitions) => definitions,
+        Err(error) => {
+            eprintln!("couldn't load voxel definitions from '{}': {}", VOXELS_FILE, error);
+            std::process::exit(1);
+        }
+    };
+
+    let meta = match WorldMeta::load_or_create(WORLD_DIR, &settings.world) {
+        Ok(meta) => meta,
+        Err(error) => {
+            eprintln!("couldn't open world '{}': {}", WORLD_DIR, error);
+            std::process::exit(1);
+        }
+    };
+    set_voxel_scale(meta.voxel_scale);
+
     App::new()
-        .insert_resource(Msaa { samples: 4 })
+        .insert_resource(Msaa { samples: settings.graphics.msaa })
+        .insert_resource(systems::TimeOfDay(meta.time_of_day))
+        .insert_resource(meta)
+        .insert_resource(settings)
+        .insert_resource(definitions)
+        .insert_resource(systems::SettingsWatcher(systems::FileWatcher::new(CONFIG_FILE)))
+        .insert_resource(systems::VoxelWatcher(systems::FileWatcher::new(VOXELS_FILE)))
         .add_plugins(DefaultPlugins)
+        .init_resource::<systems::Autosave>()
+        .init_resource::<systems::EvictionSaves>()
+        .init_resource::<LoadQueue>()
+        .init_resource::<Input<Action>>()
         .add_startup_system(setup)
-        .add_system(systems::keyboard_controls)
-        .add_system(systems::mouse_controls)
-        .add_system(systems::skylight)
+        .add_startup_system(systems::setup_chunk_diagnostics)
+        .add_state(systems::GameState::Paused)
+        .add_system_to_stage(CoreStage::PreUpdate, systems::update_actions.label("actions").after(InputSystem))
+        .add_system_to_stage(CoreStage::PreUpdate, systems::pause.after("actions"))
+        .add_system_set(SystemSet::on_enter(systems::GameState::Playing).with_system(systems::grab_cursor))
+        .add_system_set(SystemSet::on_enter(systems::GameState::Paused).with_system(systems::release_cursor))
+        // Gameplay, which stops while the game is paused
+        .add_system_set(SystemSet::on_update(systems::GameState::Playing)
+            .with_system(systems::toggle_movement_mode.before("movement"))
+            .with_system(systems::movement_controls.label("movement"))
+            .with_system(systems::physics.label("physics").after("movement"))
+            .with_system(systems::look_controls.label("look"))
+            .with_system(systems::toggle_camera_mode.before("camera"))
+            .with_system(systems::zoom_orbit.before("camera"))
+            .with_system(systems::follow_player.label("camera").after("physics").after("look"))
+            .with_system(systems::skylight)
+            .with_system(systems::select_voxel)
+            .with_system(systems::interact.label("interact")))
+        .add_system(systems::stream_chunks.label("stream_chunks").after("camera"))
+        .add_system(systems::cull_chunks.after("stream_chunks"))
+        .add_system(systems::reload_settings)
+        .add_system(systems::reload_voxels)
+        .add_system(systems::remesh_chunks.after("interact"))
+        .add_system(systems::autosave)
+        .add_system(systems::evict_chunks)
+        .add_system_to_stage(CoreStage::Last, systems::save_on_exit)
         .run();
 }
 
+/// Bind an action to the bindings in `args` (the action's name first), and save it to the config
+fn bind(args: Vec<String>) {
+    let fail = |message: String| -> ! {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    };
+
+    let action = match args.first() {
+        Some(name) => Action::from_name(name).unwrap_or_else(|| fail(format!("unknown action '{}'", name))),
+        None => {
+            let actions: Vec<&str> = Action::ALL.iter().map(|action| action.name()).collect();
+            fail(format!("usage: svep bind <action> <bindings...>, actions are {}", actions.join(", ")))
+        }
+    };
+    let bindings: Vec<Binding> = args[1..].iter()
+        .map(|binding| binding.parse().unwrap_or_else(|error| fail(error)))
+        .collect();
+
+    let (mut settings, _) = Settings::load(CONFIG_FILE)
+        .unwrap_or_else(|error| fail(format!("couldn't load settings from '{}': {}", CONFIG_FILE, error)));
+    if let Err(conflict) = settings.bindings.bind(action, bindings) {
+        fail(format!("couldn't bind {}: {}", action, conflict));
+    }
+    if let Err(error) = settings.bindings.save(CONFIG_FILE) {
+        fail(format!("couldn't save bindings to '{}': {}", CONFIG_FILE, error));
+    }
+
+    let bindings: Vec<String> = settings.bindings.get(action).iter().map(|binding| binding.to_string()).collect();
+    println!("bound {} to [{}]", action, bindings.join(", "));
+}
+
 fn setup(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    meta: Res<WorldMeta>,
+    settings: Res<Settings>,
+    definitions: Res<VoxelDefinitions>,
 ) {
 
-    let mut cm = ChunkManager::default();
-
-    for x in -10..10i32 {
-        for z in -10..10i32 {
-            for y in -3..3i32 {
-                commands.spawn_bundle(PbrBundle {
-                    mesh: meshes.add(cm.generate_new(IVec3::new(x, y, z)).create_mesh().into()),
-                    material: materials.add(StandardMaterial {
-                        base_color: Color::rgb(0.6, 0.6, 0.6),
-                        metallic: 0.0,
-                        perceptual_roughness: 0.6,
-                        reflectance: 0.001,
-                        .. Default::default()
-                    }),
-                    transform: Transform::from_translation(Vec3::new(x as f32 * 32.0, y as f32 * 32.0, z as f32 * 32.0)),
-                    ..Default::default()
-                });
-            }
-
+    let storage = match WorldStorage::open(WORLD_DIR) {
+        Ok(storage) => storage,
+        Err(error) => {
+            eprintln!("couldn't open world directory '{}': {}", WORLD_DIR, error);
+            std::process::exit(1);
         }
-    }
+    };
+    let mut cm = ChunkManager::with_storage(storage);
+    cm.set_generator(meta.seed, meta.generator.clone());
+    cm.set_memory_budget(Some(settings.graphics.chunk_memory * 1024 * 1024));
+
+    // Chunks are loaded around the player by `stream_chunks`
+    commands.insert_resource(cm);
+    commands.insert_resource(systems::ChunkMaterial(materials.add(definitions.solid().material())));
 
     // light
     let size = 100.0;
@@ -66,13 +189,26 @@ fn setup(
             },
             ..Default::default()
         },
-        transform: Transform::from_rotation(Quat::from_axis_angle(Vec3::X, -2.0)),
+        transform: Transform::from_rotation(systems::sun_rotation(meta.time_of_day)),
         ..Default::default()
     });
 
-    // camera + player
+    // player, back where they were when the world was last saved
+    let player = match &meta.player {
+        Some(player) => player.clone(),
+        None => components::Player::with_eyes_at(meta.spawn, (0.0, 0.0)),
+    };
+
+    // camera, which follows the player around
     commands.spawn_bundle(PerspectiveCameraBundle {
-        transform: Transform::from_xyz(0., 1.0, 0.).looking_at(Vec3::ZERO, Vec3::Y),
+        transform: player.eye(),
+        perspective_projection: PerspectiveProjection {
+            fov: settings.graphics.fov.to_radians(),
+            ..Default::default()
+        },
         ..Default::default()
-    }).insert(components::Player::default());
+    })
+        .insert(components::PlayerCamera::default());
+
+    commands.spawn().insert(player);
 }
\ No newline at end of file
@@ -0,0 +1,366 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use bevy::math::Vec2;
+use serde::{Deserialize, Serialize};
+
+use crate::components::MovementMode;
+use crate::input::Bindings;
+use crate::world::chunk::CHUNK_SIZE;
+
+/// Everything in `resources/config.toml`. Anything that isn't in the file is left at its default.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Settings {
+    pub(crate) controls: ControlSettings,
+    pub(crate) graphics: GraphicsSettings,
+    pub(crate) world: WorldSettings,
+    pub(crate) movement: MovementSettings,
+    pub(crate) bindings: Bindings
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct ControlSettings {
+    /// Degrees turned per pixel the mouse moves
+    pub(crate) mouse_sens: f32,
+    /// Degrees turned per second with the look stick pushed all the way
+    pub(crate) stick_look_speed: f32,
+    /// Gamepad stick for moving around
+    pub(crate) move_stick: StickSettings,
+    /// Gamepad stick for looking around
+    pub(crate) look_stick: StickSettings
+}
+
+impl Default for ControlSettings {
+    fn default() -> Self {
+        Self {
+            mouse_sens: 0.05,
+            stick_look_speed: 180.0,
+            move_stick: StickSettings { dead_zone: 0.15, curve: 1.0 },
+            look_stick: StickSettings { dead_zone: 0.1, curve: 2.0 }
+        }
+    }
+}
+
+/// How a gamepad stick's position is turned into movement
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct StickSettings {
+    /// How far the stick has to be pushed (from 0 to 1) before it does anything, so sticks that don't quite center
+    /// don't make the player drift
+    pub(crate) dead_zone: f32,
+    /// Exponent applied to how far the stick is pushed past the dead zone. 1 is linear, higher values give more
+    /// control over small movements.
+    pub(crate) curve: f32
+}
+
+impl StickSettings {
+    /// Apply the dead zone and response curve to the raw position of a stick. The result is never more than one long.
+    pub(crate) fn apply(&self, raw: Vec2) -> Vec2 {
+        let length = raw.length();
+        if length <= self.dead_zone {
+            return Vec2::ZERO
+        }
+
+        let pushed = ((length - self.dead_zone) / (1.0 - self.dead_zone)).min(1.0);
+        raw / length * pushed.powf(self.curve)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct GraphicsSettings {
+    /// Vertical field of view, in degrees
+    pub(crate) fov: f32,
+    /// How far away from the player chunks are loaded, in chunks
+    pub(crate) view_distance: i32,
+    pub(crate) vertical_view_distance: i32,
+    /// How many megabytes loaded chunks and their meshes may take up before old ones are unloaded
+    pub(crate) chunk_memory: usize,
+    /// Samples per pixel for anti aliasing, 1 turns it off
+    pub(crate) msaa: u32
+}
+
+impl Default for GraphicsSettings {
+    fn default() -> Self {
+        Self {
+            fov: 45.0,
+            view_distance: 10,
+            vertical_view_distance: 3,
+            chunk_memory: 1024,
+            msaa: 4
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct WorldSettings {
+    /// Seed for new worlds, random if it isn't set. Existing worlds keep their seed.
+    pub(crate) seed: Option<u32>,
+    /// Size of chunks, in voxels. This is picked when building svep, so it's only here to check that the config
+    /// matches the build.
+    pub(crate) chunk_size: usize,
+    /// How many world units large voxels are in new worlds. Existing worlds keep their voxel scale.
+    pub(crate) voxel_scale: f32,
+    /// Where shaders are loaded from
+    // todo: there aren't any custom shaders yet
+    pub(crate) shader_dir: PathBuf
+}
+
+impl Default for WorldSettings {
+    fn default() -> Self {
+        Self {
+            seed: None,
+            chunk_size: CHUNK_SIZE,
+            voxel_scale: 1.0,
+            shader_dir: PathBuf::from("resources/shaders")
+        }
+    }
+}
+
+/// How the player moves in a movement mode
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct MovementParams {
+    /// Top speed, in world units per second
+    pub(crate) speed: f32,
+    /// How quickly the player speeds up when moving, in world units per second squared
+    pub(crate) acceleration: f32,
+    /// How quickly the player slows down when they stop moving, in world units per second squared
+    pub(crate) friction: f32
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct MovementSettings {
+    pub(crate) walk: MovementParams,
+    pub(crate) fly: MovementParams,
+    pub(crate) noclip: MovementParams,
+    /// Top speed is multiplied by this while sprinting
+    pub(crate) sprint_multiplier: f32,
+    /// Top speed is multiplied by this while crouching
+    pub(crate) crouch_multiplier: f32
+}
+
+impl Default for MovementSettings {
+    fn default() -> Self {
+        Self {
+            walk: MovementParams { speed: 6.0, acceleration: 60.0, friction: 40.0 },
+            fly: MovementParams { speed: 12.0, acceleration: 40.0, friction: 20.0 },
+            noclip: MovementParams { speed: 20.0, acceleration: 80.0, friction: 60.0 },
+            sprint_multiplier: 1.6,
+            crouch_multiplier: 0.4
+        }
+    }
+}
+
+impl MovementSettings {
+    pub(crate) fn params(&self, mode: MovementMode) -> MovementParams {
+        match mode {
+            MovementMode::Walk => self.walk,
+            MovementMode::Fly => self.fly,
+            MovementMode::Noclip => self.noclip
+        }
+    }
+}
+
+/// Reasons that the settings couldn't be loaded
+#[derive(Debug)]
+pub(crate) enum SettingsError {
+    Io(io::Error),
+    /// Not valid TOML, or a value has the wrong type
+    Parse(toml::de::Error),
+    /// The config isn't valid TOML, so settings can't be written back into it
+    Edit(toml_edit::TomlError),
+    /// A value has the right type but doesn't make sense, like a negative field of view
+    Invalid { key: &'static str, reason: String }
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "{}", error),
+            Self::Parse(error) => write!(f, "{}", error),
+            Self::Edit(error) => write!(f, "{}", error),
+            Self::Invalid { key, reason } => write!(f, "invalid value for {}: {}", key, reason)
+        }
+    }
+}
+
+impl Error for SettingsError {}
+
+impl From<io::Error> for SettingsError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<toml::de::Error> for SettingsError {
+    fn from(error: toml::de::Error) -> Self {
+        Self::Parse(error)
+    }
+}
+
+impl From<toml_edit::TomlError> for SettingsError {
+    fn from(error: toml_edit::TomlError) -> Self {
+        Self::Edit(error)
+    }
+}
+
+/// Error for `key` unless `valid`
+fn check(valid: bool, key: &'static str, reason: String) -> Result<(), SettingsError> {
+    if valid {
+        Ok(())
+    } else {
+        Err(SettingsError::Invalid { key, reason })
+    }
+}
+
+impl Settings {
+    /// Read the settings from the config file at `path`, along with a warning for every key in it that isn't a
+    /// setting. If there's no config file, these are the default settings.
+    pub(crate) fn load<P: AsRef<Path>>(path: P) -> Result<(Self, Vec<String>), SettingsError> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok((Self::default(), Vec::new())),
+            Err(error) => Err(error.into())
+        }
+    }
+
+    /// Parse settings from the contents of a config file, along with a warning for every key that isn't a setting.
+    pub(crate) fn parse(text: &str) -> Result<(Self, Vec<String>), SettingsError> {
+        let mut warnings = Vec::new();
+        let mut deserializer = toml::Deserializer::new(text);
+        let settings: Self = serde_ignored::deserialize(&mut deserializer, |path| {
+            warnings.push(format!("unknown setting '{}'", path));
+        })?;
+        deserializer.end()?;
+
+        settings.validate()?;
+        Ok((settings, warnings))
+    }
+
+    fn validate(&self) -> Result<(), SettingsError> {
+        let controls = &self.controls;
+        check(controls.mouse_sens > 0.0, "controls.mouse_sens", format!("must be more than 0, got {}", controls.mouse_sens))?;
+        check(controls.stick_look_speed > 0.0, "controls.stick_look_speed", format!("must be more than 0, got {}", controls.stick_look_speed))?;
+        for (key, stick) in [("controls.move_stick", controls.move_stick), ("controls.look_stick", controls.look_stick)] {
+            check(
+                stick.dead_zone >= 0.0 && stick.dead_zone < 1.0 && stick.curve > 0.0,
+                key,
+                format!("dead zone must be at least 0 and less than 1 and curve must be more than 0, got {:?}", stick)
+            )?;
+        }
+
+        let graphics = &self.graphics;
+        check(graphics.fov > 0.0 && graphics.fov < 180.0, "graphics.fov", format!("must be between 0 and 180 degrees, got {}", graphics.fov))?;
+        check(graphics.view_distance >= 1, "graphics.view_distance", format!("must be at least 1, got {}", graphics.view_distance))?;
+        check(graphics.vertical_view_distance >= 1, "graphics.vertical_view_distance", format!("must be at least 1, got {}", graphics.vertical_view_distance))?;
+        check(graphics.chunk_memory >= 1, "graphics.chunk_memory", format!("must be at least 1 megabyte, got {}", graphics.chunk_memory))?;
+        // The only sample counts that bevy supports right now
+        check(matches!(graphics.msaa, 1 | 4), "graphics.msaa", format!("must be 1 (off) or 4, got {}", graphics.msaa))?;
+
+        check(
+            self.world.chunk_size == CHUNK_SIZE,
+            "world.chunk_size",
+            format!("this build of svep uses {} voxel chunks, got {} (chunk size is picked with the chunk-16 and chunk-64 features when building)", CHUNK_SIZE, self.world.chunk_size)
+        )?;
+        check(
+            self.world.voxel_scale.is_finite() && self.world.voxel_scale > 0.0,
+            "world.voxel_scale",
+            format!("must be more than 0, got {}", self.world.voxel_scale)
+        )?;
+
+        let movement = &self.movement;
+        for (key, params) in [("movement.walk", movement.walk), ("movement.fly", movement.fly), ("movement.noclip", movement.noclip)] {
+            check(
+                params.speed >= 0.0 && params.acceleration > 0.0 && params.friction > 0.0,
+                key,
+                format!("speed can't be negative and acceleration and friction must be more than 0, got {:?}", params)
+            )?;
+        }
+        check(movement.sprint_multiplier > 0.0, "movement.sprint_multiplier", format!("must be more than 0, got {}", movement.sprint_multiplier))?;
+        check(movement.crouch_multiplier > 0.0, "movement.crouch_multiplier", format!("must be more than 0, got {}", movement.crouch_multiplier))?;
+
+        if let Some(conflict) = self.bindings.conflicts().first() {
+            return Err(SettingsError::Invalid { key: "bindings", reason: conflict.to_string() });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults() {
+        // Every setting is optional
+        let (settings, warnings) = Settings::parse("").unwrap();
+        assert_eq!(settings, Settings::default());
+        assert!(warnings.is_empty());
+
+        let (settings, _) = Settings::parse("[graphics]\nfov = 70.0\n[world]\nseed = 42").unwrap();
+        assert_eq!(settings.graphics.fov, 70.0);
+        assert_eq!(settings.graphics.msaa, GraphicsSettings::default().msaa);
+        assert_eq!(settings.world.seed, Some(42));
+
+        // The config in the repository is valid, and doesn't have anything that isn't a setting
+        let (_, warnings) = Settings::load(concat!(env!("CARGO_MANIFEST_DIR"), "/resources/config.toml")).unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+    }
+
+    #[test]
+    fn sticks() {
+        let stick = StickSettings { dead_zone: 0.2, curve: 2.0 };
+
+        // Nothing happens inside the dead zone, and pushing it all the way is full speed in any direction
+        assert_eq!(stick.apply(Vec2::new(0.1, -0.15)), Vec2::ZERO);
+        assert!((stick.apply(Vec2::new(0.0, 1.0)) - Vec2::Y).length() < 1e-6);
+        assert!((stick.apply(Vec2::new(0.9, 0.9)).length() - 1.0).abs() < 1e-6);
+
+        // Movement starts from zero at the edge of the dead zone, and small movements are smaller with the curve
+        assert!(stick.apply(Vec2::new(0.21, 0.0)).x < 1e-3);
+        assert!((stick.apply(Vec2::new(-0.6, 0.0)).x + 0.25).abs() < 1e-6);
+        let linear = StickSettings { curve: 1.0, ..stick };
+        assert!((linear.apply(Vec2::new(-0.6, 0.0)).x + 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn unknown_keys() {
+        let (settings, warnings) = Settings::parse("[controls]\nmouse_sens = 0.1\nmouse_sense = 0.2\n[sound]\nvolume = 1").unwrap();
+        assert_eq!(settings.controls.mouse_sens, 0.1);
+        assert_eq!(warnings, vec!["unknown setting 'controls.mouse_sense'", "unknown setting 'sound'"]);
+    }
+
+    #[test]
+    fn invalid() {
+        let error = Settings::parse("[graphics]\nfov = 200.0").unwrap_err();
+        assert!(matches!(error, SettingsError::Invalid { key: "graphics.fov", .. }));
+        assert_eq!(error.to_string(), "invalid value for graphics.fov: must be between 0 and 180 degrees, got 200");
+
+        let error = Settings::parse("[graphics]\nchunk_memory = 0").unwrap_err();
+        assert!(matches!(error, SettingsError::Invalid { key: "graphics.chunk_memory", .. }));
+
+        let error = Settings::parse("[world]\nchunk_size = 7").unwrap_err();
+        assert!(matches!(error, SettingsError::Invalid { key: "world.chunk_size", .. }));
+
+        let error = Settings::parse("[world]\nvoxel_scale = nan").unwrap_err();
+        assert!(matches!(error, SettingsError::Invalid { key: "world.voxel_scale", .. }));
+
+        let error = Settings::parse("[controls.look_stick]\ndead_zone = 1.0\ncurve = 1.0").unwrap_err();
+        assert!(matches!(error, SettingsError::Invalid { key: "controls.look_stick", .. }));
+
+        let error = Settings::parse("[movement.walk]\nspeed = 1.0\nacceleration = 0.0\nfriction = 1.0").unwrap_err();
+        assert!(matches!(error, SettingsError::Invalid { key: "movement.walk", .. }));
+
+        // Wrong types say where they are
+        let error = Settings::parse("[graphics]\n\nmsaa = \"lots\"").unwrap_err();
+        assert!(matches!(error, SettingsError::Parse(_)));
+        assert!(error.to_string().contains("line 3"), "{}", error);
+    }
+}
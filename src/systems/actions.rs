@@ -0,0 +1,70 @@
+use bevy::prelude::*;
+
+use crate::input::Action;
+use crate::settings::Settings;
+
+/// Press and release actions in `Input<Action>` when anything they're bound to is pressed or released.
+/// Runs right after bevy updates the keyboard, mouse and gamepad input, so actions are up to date for every system.
+pub(crate) fn update_actions(
+    settings: Res<Settings>,
+    keys: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    gamepad: Res<Input<GamepadButton>>,
+    mut actions: ResMut<Input<Action>>
+) {
+    actions.clear();
+
+    for action in Action::ALL {
+        let pressed = settings.bindings.get(action).iter().any(|binding| binding.pressed(&keys, &mouse, &gamepad));
+        if pressed {
+            actions.press(action);
+        } else if actions.pressed(action) {
+            // Releasing something that isn't pressed would still count as just released
+            actions.release(action);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::Binding;
+
+    #[test]
+    fn actions() {
+        let mut world = World::new();
+        let mut settings = Settings::default();
+        settings.bindings.bind(Action::Jump, vec![Binding::Key(KeyCode::Space), Binding::Gamepad(GamepadButtonType::South)]).unwrap();
+        world.insert_resource(settings);
+        world.insert_resource(Input::<KeyCode>::default());
+        world.insert_resource(Input::<MouseButton>::default());
+        world.insert_resource(Input::<GamepadButton>::default());
+        world.insert_resource(Input::<Action>::default());
+
+        let mut stage = SystemStage::single(update_actions);
+
+        world.get_resource_mut::<Input<MouseButton>>().unwrap().press(MouseButton::Left);
+        stage.run(&mut world);
+        let actions = world.get_resource::<Input<Action>>().unwrap();
+        assert!(actions.just_pressed(Action::Break));
+        assert!(!actions.pressed(Action::Place));
+        assert!(!actions.just_released(Action::Place));
+
+        // Still held, but not just pressed anymore
+        stage.run(&mut world);
+        assert!(world.get_resource::<Input<Action>>().unwrap().pressed(Action::Break));
+        assert!(!world.get_resource::<Input<Action>>().unwrap().just_pressed(Action::Break));
+
+        // Either binding works
+        world.get_resource_mut::<Input<GamepadButton>>().unwrap().press(GamepadButton(Gamepad(0), GamepadButtonType::South));
+        stage.run(&mut world);
+        assert!(world.get_resource::<Input<Action>>().unwrap().just_pressed(Action::Jump));
+
+        world.get_resource_mut::<Input<GamepadButton>>().unwrap().release(GamepadButton(Gamepad(0), GamepadButtonType::South));
+        world.get_resource_mut::<Input<MouseButton>>().unwrap().release(MouseButton::Left);
+        stage.run(&mut world);
+        let actions = world.get_resource::<Input<Action>>().unwrap();
+        assert!(actions.just_released(Action::Jump));
+        assert!(actions.just_released(Action::Break));
+    }
+}
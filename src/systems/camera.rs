@@ -1,53 +1,323 @@
 use bevy::prelude::*;
 use std::f32::consts::PI;
-use bevy::input::mouse::MouseMotion;
-use crate::components::Player;
+use bevy::input::mouse::{MouseMotion, MouseScrollUnit, MouseWheel};
+use crate::components::{look_angles, look_rotation, CameraMode, Orbit, Player, PlayerCamera, Rotation};
+use crate::input::{Action, Stick};
+use crate::settings::Settings;
+use crate::world::coords::FloatPos;
+use crate::world::manager::ChunkManager;
 
-const MOUSE_SENSITIVITY: f32 = 0.05;
+/// How far behind the player's eyes the camera is in third person, in world units
+const THIRD_PERSON_DISTANCE: f32 = 4.0;
 
-type Rotation = (f32, f32);
+/// How far the camera stays away from voxels in third person, so it doesn't end up inside them
+const CAMERA_MARGIN: f32 = 0.2;
 
-pub(crate) fn mouse_controls(mut rot: Local<Rotation>, mut events: EventReader<MouseMotion>, mut player: Query<&mut Transform, With<Player>>) {
-    let mut pitch: f32 = 0.0;
-    let mut yaw: f32 = 0.0;
+/// How far away the voxel the player is looking at can be to orbit around it, in world units
+const ORBIT_RANGE: f32 = 64.0;
 
-    for mouse in events.iter() {
-        pitch += mouse.delta.y * MOUSE_SENSITIVITY;
-        yaw += -mouse.delta.x * MOUSE_SENSITIVITY;
-    }
+/// How far in front of the player the camera orbits if they aren't looking at a voxel, in world units
+const ORBIT_DISTANCE: f32 = 8.0;
+
+const MIN_ORBIT_DISTANCE: f32 = 1.0;
+const MAX_ORBIT_DISTANCE: f32 = 128.0;
 
+/// Seconds it takes the camera to get where it should be after switching camera modes
+const TRANSITION_TIME: f32 = 0.3;
+
+/// Turn `rot` by `pitch` and `yaw` degrees, without looking further up or down than straight up or down
+fn turn(rot: &mut Rotation, pitch: f32, yaw: f32) {
     let new_pitch = rot.0 + (pitch * PI/180.0);
     if (-PI/2.0..=PI/2.0).contains(&new_pitch) {
         rot.0 = new_pitch;
     }
     rot.1 += yaw * PI/180.0;
+}
+
+impl Orbit {
+    /// Orbit around whatever the player is looking at from `eye`, starting out right where their eyes are
+    pub(crate) fn around(chunks: &ChunkManager, eye: &Transform) -> Self {
+        let distance = chunks.raycast(FloatPos(eye.translation), eye.forward(), ORBIT_RANGE, |voxel| voxel.active)
+            .map_or(ORBIT_DISTANCE, |hit| hit.distance)
+            .max(MIN_ORBIT_DISTANCE);
+
+        Self {
+            focus: eye.translation + eye.forward() * distance,
+            angles: look_angles(eye.rotation),
+            distance
+        }
+    }
+
+    pub(crate) fn transform(&self) -> Transform {
+        let rotation = look_rotation(self.angles);
+        Transform::from_translation(self.focus + rotation * Vec3::Z * self.distance).with_rotation(rotation)
+    }
+}
+
+/// Where the camera should be in third person, behind `eye` or closer to it if there's a voxel in the way
+pub(crate) fn third_person_position(chunks: &ChunkManager, eye: &Transform) -> Vec3 {
+    let back = -eye.forward();
+    let distance = match chunks.raycast(FloatPos(eye.translation), back, THIRD_PERSON_DISTANCE + CAMERA_MARGIN, |voxel| voxel.active) {
+        Some(hit) => (hit.distance - CAMERA_MARGIN).max(0.0),
+        None => THIRD_PERSON_DISTANCE
+    };
+
+    eye.translation + back * distance
+}
+
+/// Where `camera` should be when the player's eyes are at `eye`
+pub(crate) fn camera_target(camera: &PlayerCamera, chunks: &ChunkManager, eye: &Transform) -> Transform {
+    match camera.mode {
+        CameraMode::FirstPerson => Transform::from_translation(eye.translation).with_rotation(eye.rotation),
+        CameraMode::ThirdPerson => Transform::from_translation(third_person_position(chunks, eye)).with_rotation(eye.rotation),
+        CameraMode::Orbit => camera.orbit.transform()
+    }
+}
+
+/// Look around with the mouse, or with the right stick on a gamepad. In orbit mode this moves the camera around
+/// instead.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn look_controls(
+    time: Res<Time>,
+    settings: Res<Settings>,
+    gamepads: Res<Gamepads>,
+    axes: Res<Axis<GamepadAxis>>,
+    mut events: EventReader<MouseMotion>,
+    mut player: Query<&mut Player>,
+    mut camera: Query<&mut PlayerCamera>
+) {
+    let controls = &settings.controls;
+    let sensitivity = controls.mouse_sens;
+    let mut pitch: f32 = 0.0;
+    let mut yaw: f32 = 0.0;
+
+    for mouse in events.iter() {
+        pitch += mouse.delta.y * sensitivity;
+        yaw += -mouse.delta.x * sensitivity;
+    }
+
+    // Down is positive for the mouse, but up is positive for the stick
+    let stick = Stick::Right.read(&gamepads, &axes, &controls.look_stick) * controls.stick_look_speed * time.delta_seconds();
+    pitch -= stick.y;
+    yaw -= stick.x;
+
+    if let Ok(mut camera) = camera.get_single_mut() {
+        if camera.mode == CameraMode::Orbit {
+            turn(&mut camera.orbit.angles, pitch, yaw);
+            return
+        }
+    }
+
+    if pitch != 0.0 || yaw != 0.0 {
+        turn(&mut player.single_mut().look, pitch, yaw);
+    }
+}
+
+/// Zoom in and out with the scroll wheel in orbit mode
+pub(crate) fn zoom_orbit(mut wheel: EventReader<MouseWheel>, mut camera: Query<&mut PlayerCamera>) {
+    let scrolled: f32 = wheel.iter()
+        .map(|event| match event.unit {
+            MouseScrollUnit::Line => event.y,
+            // Roughly how many pixels a line is
+            MouseScrollUnit::Pixel => event.y / 20.0
+        })
+        .sum();
+
+    let mut camera = camera.single_mut();
+    if scrolled == 0.0 || camera.mode != CameraMode::Orbit {
+        return
+    }
 
+    let orbit = &mut camera.orbit;
+    orbit.distance = (orbit.distance * 0.9f32.powf(scrolled)).clamp(MIN_ORBIT_DISTANCE, MAX_ORBIT_DISTANCE);
+}
+
+/// Switch between first person, third person and orbiting. When going back from orbiting the player turns to look
+/// where the camera was looking.
+pub(crate) fn toggle_camera_mode(
+    actions: Res<Input<Action>>,
+    chunks: Res<ChunkManager>,
+    mut player: Query<&mut Player>,
+    mut camera: Query<(&Transform, &mut PlayerCamera)>
+) {
+    if !actions.just_pressed(Action::ToggleCamera) {
+        return
+    }
 
-    let mut trans = player.single_mut();
-    trans.rotation = Quat::from_axis_angle(Vec3::Y, rot.1) * Quat::from_axis_angle(-Vec3::X, rot.0);
+    let mut player = player.single_mut();
+    let (transform, mut camera) = camera.single_mut();
+
+    let next = camera.mode.next();
+    if next == CameraMode::Orbit {
+        camera.orbit = Orbit::around(&chunks, &player.eye());
+    } else if camera.mode == CameraMode::Orbit {
+        player.look = camera.orbit.angles;
+    }
+
+    camera.mode = next;
+    camera.transition = Some((*transform, 0.0));
+    info!("Camera mode: {:?}", camera.mode);
 }
 
-pub(crate) fn keyboard_controls(kb: Res<Input<KeyCode>>, mut player: Query<&mut Transform, With<Player>>) {
-    let mut trans = player.single_mut();
-    let local_fwd = trans.forward();
-    let local_right = trans.right();
+/// Where `camera` is `delta` seconds further into moving from where it was when the mode changed to `target`, or just
+/// `target` if it already got there
+fn transition(camera: &mut PlayerCamera, mut target: Transform, delta: f32) -> Transform {
+    if let Some((from, progress)) = camera.transition {
+        let progress = (progress + delta / TRANSITION_TIME).min(1.0);
+        // Starts and ends slowly
+        let eased = progress * progress * (3.0 - 2.0 * progress);
+        target.translation = from.translation.lerp(target.translation, eased);
+        target.rotation = from.rotation.slerp(target.rotation, eased);
+        camera.transition = if progress < 1.0 { Some((from, progress)) } else { None };
+    }
+
+    target
+}
 
-    if kb.pressed(KeyCode::Space) {
-        trans.translation.y += 0.125;
+/// Move the camera to where it should be for its mode, smoothly if the mode just changed
+pub(crate) fn follow_player(
+    time: Res<Time>,
+    chunks: Res<ChunkManager>,
+    player: Query<&Player>,
+    mut camera: Query<(&mut Transform, &mut PlayerCamera)>
+) {
+    let eye = player.single().eye();
+    let (mut transform, mut camera) = camera.single_mut();
+    let target = camera_target(&camera, &chunks, &eye);
+    let target = transition(&mut camera, target, time.delta_seconds());
+
+    // Only write when it moved, so a still camera doesn't trigger change detection
+    if *transform != target {
+        *transform = target;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::Volume;
+    use crate::world::chunk::{voxel_scale, Chunk};
+    use crate::world::coords::{ChunkPos, WorldPos};
+    use crate::world::voxel::Voxel;
+
+    /// An empty chunk with a wall at z = 10
+    fn wall() -> ChunkManager {
+        let mut chunks = ChunkManager::default();
+        chunks.insert(Chunk::new(ChunkPos::new(0, 0, 0), Volume::filled(Voxel::inactive())));
+        for x in 0..8 {
+            for y in 0..8 {
+                chunks.set_voxel(WorldPos::new(x, y, 10), Voxel::active());
+            }
+        }
+        chunks
+    }
+
+    /// Eyes at `z` (in voxels), looking away from the wall
+    fn eye(z: f32) -> Transform {
+        let position = Vec3::new(4.5, 4.5, z) * voxel_scale();
+        Transform::from_translation(position).looking_at(position - Vec3::Z, Vec3::Y)
     }
-    if kb.pressed(KeyCode::LShift) {
-        trans.translation.y -= 0.125;
+
+    #[test]
+    fn third_person() {
+        let chunks = wall();
+
+        // Nothing in the way
+        let far = eye(10.0 - (THIRD_PERSON_DISTANCE + 1.0) / voxel_scale());
+        assert!((third_person_position(&chunks, &far) - (far.translation + Vec3::Z * THIRD_PERSON_DISTANCE)).length() < 1e-5);
+
+        // The wall is in the way, so the camera stays in front of it
+        let close = eye(9.0);
+        let position = third_person_position(&chunks, &close);
+        assert!((position.z - (10.0 * voxel_scale() - CAMERA_MARGIN)).abs() < 1e-4);
+        assert_eq!(position.x, close.translation.x);
     }
-    if kb.pressed(KeyCode::W) {
-        trans.translation += local_fwd * 0.125;
+
+    #[test]
+    fn orbit() {
+        // Looking at the wall from 5 voxels away
+        let chunks = wall();
+        let eye = Transform::from_translation(Vec3::new(4.5, 4.5, 5.0) * voxel_scale()).looking_at(Vec3::new(4.5, 4.5, 20.0), Vec3::Y);
+        let orbit = Orbit::around(&chunks, &eye);
+        assert!((orbit.focus.z - 10.0 * voxel_scale()).abs() < 1e-4);
+
+        // Starts out where the player is, looking the same way
+        let transform = orbit.transform();
+        assert!((transform.translation - eye.translation).length() < 1e-4);
+        assert!((transform.forward() - eye.forward()).length() < 1e-5);
+
+        // Turning around keeps the focus in the middle of the view
+        let turned = Orbit { angles: (0.3, orbit.angles.1 + 1.0), ..orbit }.transform();
+        assert!((turned.translation + turned.forward() * orbit.distance - orbit.focus).length() < 1e-4);
     }
-    if kb.pressed(KeyCode::S) {
-        trans.translation -= local_fwd * 0.125;
+
+    #[test]
+    fn smooth_switching() {
+        let chunks = wall();
+        let eye = eye(4.0);
+        let mut camera = PlayerCamera { mode: CameraMode::ThirdPerson, transition: Some((eye, 0.0)), ..Default::default() };
+        let target = camera_target(&camera, &chunks, &eye);
+
+        // Moves further back every frame, always looking the same way, until it gets there
+        let mut last = eye.translation;
+        for _ in 0..9 {
+            let transform = transition(&mut camera, target, TRANSITION_TIME / 10.0);
+            assert!(transform.translation.z > last.z && transform.translation.z < target.translation.z);
+            assert!((transform.forward() - eye.forward()).length() < 1e-5);
+            last = transform.translation;
+        }
+        assert!((transition(&mut camera, target, TRANSITION_TIME / 10.0).translation - target.translation).length() < 1e-4);
+        assert!(camera.transition.is_none());
+        assert_eq!(transition(&mut camera, target, 1.0), target);
     }
-    if kb.pressed(KeyCode::D) {
-        trans.translation += local_right * 0.125;
+
+    #[test]
+    fn switching_modes() {
+        let mut world = World::new();
+        world.insert_resource(wall());
+        world.insert_resource(Input::<Action>::default());
+
+        let eye = eye(4.0);
+        let player = world.spawn().insert(Player::with_eyes_at(eye.translation, look_angles(eye.rotation))).id();
+        let camera = world.spawn().insert_bundle((eye, PlayerCamera::default())).id();
+
+        let mut stage = SystemStage::single(toggle_camera_mode);
+        let mut switch = |world: &mut World| {
+            let mut actions = world.get_resource_mut::<Input<Action>>().unwrap();
+            actions.clear();
+            actions.release(Action::ToggleCamera);
+            actions.press(Action::ToggleCamera);
+            stage.run(world);
+            world.get::<PlayerCamera>(camera).unwrap().mode
+        };
+
+        assert_eq!(switch(&mut world), CameraMode::ThirdPerson);
+        assert_eq!(world.get::<PlayerCamera>(camera).unwrap().transition, Some((eye, 0.0)));
+        assert_eq!(switch(&mut world), CameraMode::Orbit);
+
+        // Going back to first person after orbiting looks where the orbiting camera was looking
+        world.get_mut::<PlayerCamera>(camera).unwrap().orbit.angles = (0.2, 1.0);
+        assert_eq!(switch(&mut world), CameraMode::FirstPerson);
+        assert_eq!(world.get::<Player>(player).unwrap().look, (0.2, 1.0));
     }
-    if kb.pressed(KeyCode::A) {
-        trans.translation -= local_right * 0.125;
+
+    #[test]
+    fn following() {
+        let mut world = World::new();
+        world.insert_resource(wall());
+        world.insert_resource(Time::default());
+
+        let player = world.spawn().insert(Player::with_eyes_at(Vec3::new(4.5, 4.5, 4.0) * voxel_scale(), (0.1, 2.0))).id();
+        let camera = world.spawn().insert_bundle((Transform::default(), PlayerCamera::default())).id();
+
+        // The camera goes wherever the player's state says their eyes are
+        let mut stage = SystemStage::single(follow_player);
+        stage.run(&mut world);
+        assert_eq!(*world.get::<Transform>(camera).unwrap(), world.get::<Player>(player).unwrap().eye());
+
+        world.get_mut::<Player>(player).unwrap().position.y += 1.0;
+        world.get_mut::<Player>(player).unwrap().look = (-0.4, 0.0);
+        stage.run(&mut world);
+        assert_eq!(*world.get::<Transform>(camera).unwrap(), world.get::<Player>(player).unwrap().eye());
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,218 @@
+use bevy::diagnostic::{Diagnostic, DiagnosticId, Diagnostics};
+use bevy::prelude::*;
+use bevy::tasks::IoTaskPool;
+
+use crate::components::{ChunkEntity, PlayerCamera};
+use crate::settings::{GraphicsSettings, Settings};
+use crate::systems::EvictionSaves;
+use crate::world::coords::{ChunkPos, FloatPos};
+use crate::world::manager::ChunkManager;
+use crate::world::queue::{LoadQueue, LoadView};
+
+/// At most this many chunks are generated and meshed each frame, so loading doesn't stall the game
+const CHUNKS_PER_FRAME: usize = 8;
+
+pub(crate) const LOADED_CHUNKS: DiagnosticId = DiagnosticId::from_u128(0x8c5e_1d2a_4b7f_4e31_9a06_5f3c_d4e8_b201);
+pub(crate) const CHUNK_MEMORY: DiagnosticId = DiagnosticId::from_u128(0x8c5e_1d2a_4b7f_4e31_9a06_5f3c_d4e8_b202);
+pub(crate) const CHUNK_CACHE_HITS: DiagnosticId = DiagnosticId::from_u128(0x8c5e_1d2a_4b7f_4e31_9a06_5f3c_d4e8_b203);
+pub(crate) const CHUNK_CACHE_MISSES: DiagnosticId = DiagnosticId::from_u128(0x8c5e_1d2a_4b7f_4e31_9a06_5f3c_d4e8_b204);
+pub(crate) const CHUNK_EVICTIONS: DiagnosticId = DiagnosticId::from_u128(0x8c5e_1d2a_4b7f_4e31_9a06_5f3c_d4e8_b205);
+pub(crate) const CHUNK_CACHE_HIT_RATE: DiagnosticId = DiagnosticId::from_u128(0x8c5e_1d2a_4b7f_4e31_9a06_5f3c_d4e8_b206);
+
+/// Material that every chunk is rendered with
+pub(crate) struct ChunkMaterial(pub(crate) Handle<StandardMaterial>);
+
+/// How far chunks are loaded and shown along each axis
+fn view_range(graphics: &GraphicsSettings) -> IVec3 {
+    IVec3::new(graphics.view_distance, graphics.vertical_view_distance, graphics.view_distance)
+}
+
+
+/// Queue the chunks around the player whenever they move into another chunk (or the view distance changes), then load
+/// and mesh the most urgent ones
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn stream_chunks(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    material: Res<ChunkMaterial>,
+    settings: Res<Settings>,
+    mut chunks: ResMut<ChunkManager>,
+    mut queue: ResMut<LoadQueue>,
+    mut last_view: Local<Option<(ChunkPos, IVec3)>>,
+    camera: Query<(&Transform, &PerspectiveProjection), With<PlayerCamera>>
+) {
+    let (transform, projection) = camera.single();
+    let center = FloatPos(transform.translation).chunk();
+    let range = view_range(&settings.graphics);
+
+    if *last_view != Some((center, range)) {
+        *last_view = Some((center, range));
+        queue.retain(|pos| pos.within(center, range));
+
+        for x in -range.x..=range.x {
+            for z in -range.z..=range.z {
+                for y in -range.y..=range.y {
+                    let pos = center + IVec3::new(x, y, z);
+                    if chunks.get(pos).is_none() {
+                        queue.request(pos);
+                    }
+                }
+            }
+        }
+
+        // Keep the chunks around the player loaded, even if they can't be seen right now
+        chunks.keep_loaded(center, range);
+    }
+
+    queue.set_view(LoadView::new(transform, projection));
+
+    for _ in 0..CHUNKS_PER_FRAME {
+        let pos = match queue.pop() {
+            Some(pos) => pos,
+            None => break
+        };
+
+        let mesh = chunks.load_or_generate(pos).create_mesh();
+        chunks.record_mesh(pos, mesh.memory_size());
+
+        commands.spawn_bundle(PbrBundle {
+            mesh: meshes.add(mesh.into()),
+            material: material.0.clone(),
+            transform: Transform::from_translation(pos.corner().0),
+            ..Default::default()
+        }).insert(ChunkEntity(pos));
+    }
+}
+
+/// Make the meshes of chunks that changed since they were meshed again
+pub(crate) fn remesh_chunks(
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut chunks: ResMut<ChunkManager>,
+    entities: Query<(&ChunkEntity, &Handle<Mesh>)>
+) {
+    let changed = chunks.take_remesh();
+    if changed.is_empty() {
+        return
+    }
+
+    for (chunk, handle) in entities.iter() {
+        if !changed.contains(&chunk.0) {
+            continue;
+        }
+
+        let mesh = match chunks.get(chunk.0) {
+            Some(loaded) => loaded.create_mesh(),
+            None => continue
+        };
+        chunks.record_mesh(chunk.0, mesh.memory_size());
+
+        if let Some(old) = meshes.get_mut(handle) {
+            *old = mesh.into();
+        }
+    }
+}
+
+/// Hide chunk entities that can't be seen from the camera, because they're outside the frustum or behind terrain
+pub(crate) fn cull_chunks(
+    settings: Res<Settings>,
+    mut chunks: ResMut<ChunkManager>,
+    camera: Query<(&Transform, &PerspectiveProjection), With<PlayerCamera>>,
+    mut entities: Query<(&ChunkEntity, &mut Visibility)>
+) {
+    let (transform, projection) = camera.single();
+    let visible = chunks.update_visibility(&LoadView::new(transform, projection), view_range(&settings.graphics));
+
+    for (chunk, mut visibility) in entities.iter_mut() {
+        let is_visible = visible.contains(&chunk.0);
+
+        // Only write when it changes, so we don't trigger change detection for every chunk every frame
+        if visibility.is_visible != is_visible {
+            visibility.is_visible = is_visible;
+        }
+    }
+}
+
+/// Register the chunk cache diagnostics, so they can be shown with e.g. `LogDiagnosticsPlugin`
+pub(crate) fn setup_chunk_diagnostics(mut diagnostics: ResMut<Diagnostics>) {
+    diagnostics.add(Diagnostic::new(LOADED_CHUNKS, "loaded_chunks", 20));
+    diagnostics.add(Diagnostic::new(CHUNK_MEMORY, "chunk_memory", 20).with_suffix("MiB"));
+    diagnostics.add(Diagnostic::new(CHUNK_CACHE_HITS, "chunk_cache_hits", 20));
+    diagnostics.add(Diagnostic::new(CHUNK_CACHE_MISSES, "chunk_cache_misses", 20));
+    diagnostics.add(Diagnostic::new(CHUNK_EVICTIONS, "chunk_evictions", 20));
+    diagnostics.add(Diagnostic::new(CHUNK_CACHE_HIT_RATE, "chunk_cache_hit_rate", 20).with_suffix("%"));
+}
+
+/// Unload chunks when they take up too much memory, and despawn the entities rendering them. Changed chunks are saved
+/// in the background first.
+pub(crate) fn evict_chunks(
+    mut commands: Commands,
+    pool: Res<IoTaskPool>,
+    mut saves: ResMut<EvictionSaves>,
+    mut chunks: ResMut<ChunkManager>,
+    mut diagnostics: ResMut<Diagnostics>,
+    query: Query<(Entity, &ChunkEntity)>
+) {
+    saves.poll(&mut chunks);
+    let eviction = chunks.evict();
+    saves.start(&pool, &chunks, eviction.to_save);
+
+    if !eviction.unloaded.is_empty() {
+        // Despawning drops the mesh handle, which frees the mesh
+        for (entity, chunk) in query.iter() {
+            if eviction.unloaded.contains(&chunk.0) {
+                commands.entity(entity).despawn();
+            }
+        }
+    }
+
+    let stats = chunks.stats();
+    diagnostics.add_measurement(LOADED_CHUNKS, chunks.loaded_chunks() as f64);
+    diagnostics.add_measurement(CHUNK_MEMORY, chunks.memory_usage() as f64 / (1024.0 * 1024.0));
+    diagnostics.add_measurement(CHUNK_CACHE_HITS, stats.hits as f64);
+    diagnostics.add_measurement(CHUNK_CACHE_MISSES, stats.misses as f64);
+    diagnostics.add_measurement(CHUNK_EVICTIONS, stats.evictions as f64);
+    if let Some(rate) = stats.hit_rate() {
+        diagnostics.add_measurement(CHUNK_CACHE_HIT_RATE, rate * 100.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::Volume;
+    use crate::world::chunk::{Chunk, CHUNK_SIZE};
+    use crate::world::voxel::Voxel;
+
+    #[test]
+    fn culling_without_a_window() {
+        let mut world = World::new();
+
+        // A wall of solid chunks in front of the camera, hiding an open chunk behind it
+        let mut chunks = ChunkManager::default();
+        chunks.insert(Chunk::new(ChunkPos::new(0, 0, 0), Volume::filled(Voxel::inactive())));
+        for x in -2..=2 {
+            for y in -2..=2 {
+                chunks.insert(Chunk::new(ChunkPos::new(x, y, 1), Volume::filled(Voxel::active())));
+            }
+        }
+        chunks.insert(Chunk::new(ChunkPos::new(0, 0, 2), Volume::filled(Voxel::inactive())));
+        world.insert_resource(chunks);
+        world.insert_resource(Settings::default());
+
+        let pos = Vec3::splat(CHUNK_SIZE as f32 / 2.0);
+        world.spawn()
+            .insert(Transform::from_translation(pos).looking_at(pos + Vec3::Z, Vec3::Y))
+            .insert(PerspectiveProjection::default())
+            .insert(PlayerCamera::default());
+
+        let wall = world.spawn().insert_bundle((ChunkEntity(ChunkPos::new(0, 0, 1)), Visibility::default())).id();
+        let hidden = world.spawn().insert_bundle((ChunkEntity(ChunkPos::new(0, 0, 2)), Visibility::default())).id();
+
+        let mut stage = SystemStage::single(cull_chunks);
+        stage.run(&mut world);
+
+        assert!(world.get::<Visibility>(wall).unwrap().is_visible);
+        assert!(!world.get::<Visibility>(hidden).unwrap().is_visible);
+        assert!(!world.get_resource::<ChunkManager>().unwrap().visible_chunks().contains(&ChunkPos::new(0, 0, 2)));
+    }
+}
@@ -0,0 +1,154 @@
+use bevy::prelude::*;
+
+use crate::components::{CameraMode, Player, PlayerCamera};
+use crate::input::Action;
+use crate::world::coords::FloatPos;
+use crate::world::manager::ChunkManager;
+use crate::world::voxel::Voxel;
+
+/// How far away the player can break and place voxels, in world units
+const REACH: f32 = 8.0;
+
+/// Pick which way placed voxels are aligned (with the number keys by default), by picking their slot in the hotbar
+pub(crate) fn select_voxel(actions: Res<Input<Action>>, mut player: Query<&mut Player>) {
+    let slot = if actions.just_pressed(Action::AlignX) {
+        0
+    } else if actions.just_pressed(Action::AlignY) {
+        1
+    } else if actions.just_pressed(Action::AlignZ) {
+        2
+    } else {
+        return
+    };
+
+    player.single_mut().slot = slot;
+}
+
+/// Break the voxel the player is looking at, or place the selected voxel against it (left and right click by default).
+/// Voxels aren't placed where they would end up inside the player. Nothing happens in orbit mode, since the middle of
+/// the screen isn't where the player is looking then.
+pub(crate) fn interact(
+    actions: Res<Input<Action>>,
+    mut chunks: ResMut<ChunkManager>,
+    player: Query<&Player>,
+    camera: Query<&PlayerCamera>
+) {
+    let breaking = actions.just_pressed(Action::Break);
+    let placing = actions.just_pressed(Action::Place);
+    if !breaking && !placing {
+        return
+    }
+
+    if matches!(camera.get_single(), Ok(camera) if camera.mode == CameraMode::Orbit) {
+        return
+    }
+
+    let player = player.single();
+    let eye = player.eye();
+    let hit = match chunks.raycast(FloatPos(eye.translation), eye.forward(), REACH, |voxel| voxel.active) {
+        Some(hit) => hit,
+        None => return
+    };
+
+    if breaking {
+        chunks.set_voxel(hit.pos, Voxel::inactive());
+        return
+    }
+
+    // No face to place against if the player is inside the voxel
+    let pos = match hit.adjacent() {
+        Some(pos) => pos,
+        None => return
+    };
+
+    if Player::collider().overlaps_voxel(player.position, pos) {
+        return
+    }
+
+    chunks.set_voxel(pos, player.selected());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+    use crate::components::EYE_HEIGHT;
+    use crate::util::{Axis, Volume};
+    use crate::world::chunk::{voxel_scale, Chunk};
+    use crate::world::coords::{ChunkPos, WorldPos};
+
+    /// A world with a floor at y = 0 and a player with their eyes at `eye`, looking straight down
+    fn world(eye: Vec3) -> World {
+        let mut world = World::new();
+
+        let mut chunks = ChunkManager::default();
+        chunks.insert(Chunk::new(ChunkPos::new(0, 0, 0), Volume::filled(Voxel::inactive())));
+        for x in 0..8 {
+            for z in 0..8 {
+                chunks.set_voxel(WorldPos::new(x, 0, z), Voxel::active());
+            }
+        }
+        chunks.take_remesh();
+        world.insert_resource(chunks);
+        world.insert_resource(Input::<Action>::default());
+
+        // Placing voxels aligned with the z axis
+        world.spawn().insert(Player { slot: 2, ..Player::with_eyes_at(eye, (PI / 2.0, 0.0)) });
+
+        world
+    }
+
+    fn click(world: &mut World, action: Action) {
+        let mut actions = world.get_resource_mut::<Input<Action>>().unwrap();
+        actions.clear();
+        actions.press(action);
+        SystemStage::single(interact).run(world);
+        world.get_resource_mut::<Input<Action>>().unwrap().release(action);
+    }
+
+    fn voxel(world: &World, pos: WorldPos) -> Voxel {
+        *world.get_resource::<ChunkManager>().unwrap().voxel(pos).unwrap()
+    }
+
+    #[test]
+    fn breaking_and_placing() {
+        // Floating above the floor, out of the way of the voxel above it
+        let mut world = world(Vec3::new(2.5, 5.0, 2.5) * voxel_scale());
+
+        click(&mut world, Action::Place);
+        assert_eq!(voxel(&world, WorldPos::new(2, 1, 2)), Voxel::active().with_axis(Axis::Z));
+        assert_eq!(world.get_resource_mut::<ChunkManager>().unwrap().take_remesh(), vec![ChunkPos::new(0, 0, 0)]);
+
+        click(&mut world, Action::Break);
+        assert_eq!(voxel(&world, WorldPos::new(2, 1, 2)), Voxel::inactive());
+        click(&mut world, Action::Break);
+        assert_eq!(voxel(&world, WorldPos::new(2, 0, 2)), Voxel::inactive());
+    }
+
+    #[test]
+    fn out_of_reach() {
+        let mut world = world(Vec3::new(2.5 * voxel_scale(), voxel_scale() + REACH + 0.5, 2.5 * voxel_scale()));
+
+        click(&mut world, Action::Break);
+        assert_eq!(voxel(&world, WorldPos::new(2, 0, 2)), Voxel::active());
+    }
+
+    #[test]
+    fn not_inside_the_player() {
+        // Standing on the floor, so the voxel above it is where the player's feet are
+        let mut world = world(Vec3::new(2.5 * voxel_scale(), voxel_scale() + EYE_HEIGHT, 2.5 * voxel_scale()));
+
+        click(&mut world, Action::Place);
+        assert_eq!(voxel(&world, WorldPos::new(2, 1, 2)), Voxel::inactive());
+        assert!(world.get_resource_mut::<ChunkManager>().unwrap().take_remesh().is_empty());
+    }
+
+    #[test]
+    fn not_while_orbiting() {
+        let mut world = world(Vec3::new(2.5, 5.0, 2.5) * voxel_scale());
+        world.spawn().insert(PlayerCamera { mode: CameraMode::Orbit, ..Default::default() });
+
+        click(&mut world, Action::Break);
+        assert_eq!(voxel(&world, WorldPos::new(2, 0, 2)), Voxel::active());
+    }
+}
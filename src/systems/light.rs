@@ -1,7 +1,16 @@
 use bevy::prelude::*;
 
-pub(crate) fn skylight(mut query: Query<&mut Transform, With<DirectionalLight>>) {
+/// Angle of the sun, in radians
+pub(crate) struct TimeOfDay(pub(crate) f32);
+
+/// Rotation of the sun at `time` (see `TimeOfDay`)
+pub(crate) fn sun_rotation(time: f32) -> Quat {
+    Quat::from_axis_angle(Vec3::Z, time) * Quat::from_axis_angle(Vec3::X, -2.0)
+}
+
+pub(crate) fn skylight(mut time: ResMut<TimeOfDay>, mut query: Query<&mut Transform, With<DirectionalLight>>) {
+    time.0 += 0.0001;
     for mut light in query.iter_mut() {
-        light.rotate(Quat::from_axis_angle(Vec3::Z, 0.0001));
+        light.rotation = sun_rotation(time.0);
     }
-}
\ No newline at end of file
+}
@@ -1,5 +1,21 @@
 mod camera;
 mod light;
+mod save;
+mod chunks;
+mod interact;
+mod physics;
+mod movement;
+mod reload;
+mod actions;
+mod pause;
 
 pub(crate) use camera::*;
-pub(crate) use light::*;
\ No newline at end of file
+pub(crate) use light::*;
+pub(crate) use save::*;
+pub(crate) use chunks::*;
+pub(crate) use interact::*;
+pub(crate) use physics::*;
+pub(crate) use movement::*;
+pub(crate) use reload::*;
+pub(crate) use actions::*;
+pub(crate) use pause::*;
\ No newline at end of file
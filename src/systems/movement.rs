@@ -0,0 +1,263 @@
+use bevy::prelude::*;
+
+use crate::components::{look_rotation, MovementMode, Player};
+use crate::input::{Action, Stick};
+use crate::settings::{MovementParams, Settings};
+use crate::systems::jump;
+
+/// Change `velocity` towards moving in direction `wish` at `speed`, or towards standing still if `wish` is zero,
+/// over `delta` seconds.
+pub(crate) fn accelerate(velocity: Vec3, wish: Vec3, speed: f32, params: &MovementParams, delta: f32) -> Vec3 {
+    let target = wish * speed;
+    let rate = if wish == Vec3::ZERO { params.friction } else { params.acceleration };
+
+    let change = target - velocity;
+    let max_change = rate * delta;
+    if change.length() <= max_change {
+        target
+    } else {
+        velocity + change.normalize() * max_change
+    }
+}
+
+/// Direction the player wants to move in, from the move actions (and jump and crouch when flying) and the position of
+/// the movement stick (right and forward are positive). Never more than one long, so moving diagonally isn't any
+/// faster, but the stick can be pushed only part of the way to move slower.
+pub(crate) fn movement_input(actions: &Input<Action>, stick: Vec2, look: Quat, mode: MovementMode) -> Vec3 {
+    let (fwd, right) = (look * -Vec3::Z, look * Vec3::X);
+    let (local_fwd, local_right) = match mode {
+        // Walk along the ground, no matter if we're looking up or down
+        MovementMode::Walk => (
+            Vec3::new(fwd.x, 0.0, fwd.z).normalize_or_zero(),
+            Vec3::new(right.x, 0.0, right.z).normalize_or_zero()
+        ),
+        MovementMode::Fly | MovementMode::Noclip => (fwd, right)
+    };
+
+    let mut wish = Vec3::ZERO;
+    if actions.pressed(Action::MoveForward) {
+        wish += local_fwd;
+    }
+    if actions.pressed(Action::MoveBack) {
+        wish -= local_fwd;
+    }
+    if actions.pressed(Action::MoveRight) {
+        wish += local_right;
+    }
+    if actions.pressed(Action::MoveLeft) {
+        wish -= local_right;
+    }
+
+    // Gravity takes care of moving up and down when walking
+    if mode != MovementMode::Walk {
+        if actions.pressed(Action::Jump) {
+            wish += Vec3::Y;
+        }
+        if actions.pressed(Action::Crouch) {
+            wish -= Vec3::Y;
+        }
+    }
+
+    (wish.normalize_or_zero() + local_fwd * stick.y + local_right * stick.x).clamp_length_max(1.0)
+}
+
+/// Switch between walking, flying and noclip (with V by default)
+pub(crate) fn toggle_movement_mode(actions: Res<Input<Action>>, mut player: Query<&mut Player>) {
+    if !actions.just_pressed(Action::ToggleMovementMode) {
+        return
+    }
+
+    let mut player = player.single_mut();
+    player.mode = player.mode.next();
+    info!("Movement mode: {:?}", player.mode);
+}
+
+/// Walk around and jump, or fly around and go up and down with jump and crouch. Sprinting speeds up, and crouching
+/// slows down when walking.
+pub(crate) fn movement_controls(
+    time: Res<Time>,
+    actions: Res<Input<Action>>,
+    gamepads: Res<Gamepads>,
+    axes: Res<Axis<GamepadAxis>>,
+    settings: Res<Settings>,
+    mut player: Query<&mut Player>
+) {
+    let stick = Stick::Left.read(&gamepads, &axes, &settings.controls.move_stick);
+    let settings = &settings.movement;
+    let mut player = player.single_mut();
+    let params = settings.params(player.mode);
+    let delta = time.delta_seconds();
+
+    let mut speed = params.speed;
+    if actions.pressed(Action::Sprint) {
+        speed *= settings.sprint_multiplier;
+    } else if player.mode == MovementMode::Walk && actions.pressed(Action::Crouch) {
+        speed *= settings.crouch_multiplier;
+    }
+
+    let wish = movement_input(&actions, stick, look_rotation(player.look), player.mode);
+
+    if player.mode == MovementMode::Walk {
+        let horizontal = accelerate(Vec3::new(player.velocity.x, 0.0, player.velocity.z), wish, speed, &params, delta);
+        player.velocity.x = horizontal.x;
+        player.velocity.z = horizontal.z;
+
+        if actions.pressed(Action::Jump) {
+            jump(&mut player);
+        }
+    } else {
+        player.velocity = accelerate(player.velocity, wish, speed, &params, delta);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::MovementSettings;
+
+    #[test]
+    fn acceleration() {
+        let params = MovementSettings::default().walk;
+
+        // Speeding up takes a bit, and we never go faster than the top speed
+        let mut velocity = Vec3::ZERO;
+        velocity = accelerate(velocity, Vec3::X, params.speed, &params, 0.05);
+        assert!(velocity.x > 0.0 && velocity.x < params.speed);
+        for _ in 0..100 {
+            velocity = accelerate(velocity, Vec3::X, params.speed, &params, 0.05);
+        }
+        assert_eq!(velocity, Vec3::X * params.speed);
+
+        // Friction brings us to a stop
+        velocity = accelerate(velocity, Vec3::ZERO, params.speed, &params, 0.05);
+        assert!(velocity.x > 0.0 && velocity.x < params.speed);
+        for _ in 0..100 {
+            velocity = accelerate(velocity, Vec3::ZERO, params.speed, &params, 0.05);
+        }
+        assert_eq!(velocity, Vec3::ZERO);
+    }
+
+    #[test]
+    fn frame_rate_independent() {
+        let params = MovementSettings::default().fly;
+
+        // Speeding up for a tenth of a second ends up at the same speed at 30 and 140 frames per second
+        let run = |fps: u32| {
+            let mut velocity = Vec3::ZERO;
+            for _ in 0..fps / 10 {
+                velocity = accelerate(velocity, Vec3::Z, params.speed, &params, 1.0 / fps as f32);
+            }
+            velocity
+        };
+        assert!((run(30) - run(140)).length() < 1e-3);
+    }
+
+    #[test]
+    fn diagonal_input() {
+        let mut actions = Input::<Action>::default();
+        let looking_down = Transform::default().looking_at(Vec3::new(0.0, -1.0, -1.0), Vec3::Y).rotation;
+
+        actions.press(Action::MoveForward);
+        actions.press(Action::MoveRight);
+        let wish = movement_input(&actions, Vec2::ZERO, looking_down, MovementMode::Walk);
+        assert!((wish.length() - 1.0).abs() < 1e-5);
+        assert_eq!(wish.y, 0.0);
+
+        actions.press(Action::Jump);
+        let wish = movement_input(&actions, Vec2::ZERO, looking_down, MovementMode::Fly);
+        assert!((wish.length() - 1.0).abs() < 1e-5);
+
+        // Opposite directions cancel out
+        actions.press(Action::MoveBack);
+        actions.press(Action::MoveLeft);
+        actions.press(Action::Crouch);
+        assert_eq!(movement_input(&actions, Vec2::ZERO, looking_down, MovementMode::Fly), Vec3::ZERO);
+    }
+
+    #[test]
+    fn stick_input() {
+        let mut actions = Input::<Action>::default();
+        let look = Quat::IDENTITY;
+
+        // Pushing the stick halfway moves at half speed
+        let wish = movement_input(&actions, Vec2::new(0.0, 0.5), look, MovementMode::Walk);
+        assert!((wish - Vec3::new(0.0, 0.0, -0.5)).length() < 1e-5);
+
+        // Using the stick and the keys at the same time isn't any faster
+        actions.press(Action::MoveRight);
+        let wish = movement_input(&actions, Vec2::new(0.0, 1.0), look, MovementMode::Walk);
+        assert!((wish.length() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn gamepad() {
+        use bevy::app::Events;
+        use bevy::core::CorePlugin;
+        use bevy::input::gamepad::{GamepadEventRaw, GamepadEventType};
+        use bevy::input::{InputPlugin, InputSystem};
+        use crate::systems::{look_controls, update_actions, JUMP_SPEED};
+
+        let mut app = App::new();
+        app.add_plugin(CorePlugin)
+            .add_plugin(InputPlugin)
+            .insert_resource(Settings::default())
+            .init_resource::<Input<Action>>()
+            .add_system_to_stage(CoreStage::PreUpdate, update_actions.after(InputSystem))
+            .add_system(movement_controls)
+            .add_system(look_controls);
+        let player = app.world.spawn()
+            .insert(Player { grounded: true, ..Default::default() })
+            .id();
+
+        // Push the left stick forward, the right stick to the right, and press the jump button
+        let gamepad = Gamepad(0);
+        let mut events = app.world.get_resource_mut::<Events<GamepadEventRaw>>().unwrap();
+        events.send(GamepadEventRaw(gamepad, GamepadEventType::Connected));
+        events.send(GamepadEventRaw(gamepad, GamepadEventType::AxisChanged(GamepadAxisType::LeftStickY, 1.0)));
+        events.send(GamepadEventRaw(gamepad, GamepadEventType::AxisChanged(GamepadAxisType::RightStickX, 1.0)));
+        events.send(GamepadEventRaw(gamepad, GamepadEventType::ButtonChanged(GamepadButtonType::South, 1.0)));
+
+        // The first frame doesn't take any time
+        app.update();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        app.update();
+
+        let player = app.world.get::<Player>(player).unwrap();
+        assert_eq!(player.velocity.y, JUMP_SPEED);
+        assert!(!player.grounded);
+        assert!(player.velocity.z < 0.0);
+
+        // Turned right
+        let forward = player.eye().forward();
+        assert!(forward.x > 0.0 && forward.y.abs() < 1e-5);
+    }
+
+    #[test]
+    fn switching_modes() {
+        let mut world = World::new();
+        world.insert_resource(Input::<Action>::default());
+        let player = world.spawn().insert(Player::default()).id();
+
+        let mut stage = SystemStage::single(toggle_movement_mode);
+        let mut toggle = |world: &mut World| {
+            let mut actions = world.get_resource_mut::<Input<Action>>().unwrap();
+            actions.clear();
+            actions.release(Action::ToggleMovementMode);
+            actions.press(Action::ToggleMovementMode);
+            stage.run(world);
+        };
+
+        toggle(&mut world);
+        assert_eq!(world.get::<Player>(player).unwrap().mode, MovementMode::Fly);
+        assert!(!MovementMode::Fly.has_gravity());
+        assert!(MovementMode::Fly.collides());
+
+        toggle(&mut world);
+        assert_eq!(world.get::<Player>(player).unwrap().mode, MovementMode::Noclip);
+        assert!(!MovementMode::Noclip.collides());
+
+        toggle(&mut world);
+        assert_eq!(world.get::<Player>(player).unwrap().mode, MovementMode::Walk);
+        assert!(MovementMode::Walk.has_gravity() && MovementMode::Walk.collides());
+    }
+}
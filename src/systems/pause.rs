@@ -0,0 +1,126 @@
+use bevy::prelude::*;
+use bevy::window::WindowFocused;
+
+use crate::input::Action;
+
+/// Whether the player is playing, with the cursor grabbed, or the game is paused and the cursor is free.
+/// Gameplay systems only run while playing, chunks keep loading and saving either way.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum GameState {
+    Playing,
+    Paused
+}
+
+/// Pause when the pause action is used or the window loses focus, and start playing again when the window is
+/// clicked or the pause action is used again.
+pub(crate) fn pause(
+    mut state: ResMut<State<GameState>>,
+    mut actions: ResMut<Input<Action>>,
+    mouse: Res<Input<MouseButton>>,
+    mut focus: EventReader<WindowFocused>
+) {
+    let lost_focus = focus.iter().any(|event| !event.focused);
+
+    let next = match state.current() {
+        GameState::Playing if lost_focus || actions.just_pressed(Action::Pause) => GameState::Paused,
+        GameState::Paused if !lost_focus && (mouse.get_just_pressed().len() > 0 || actions.just_pressed(Action::Pause)) => {
+            // The click that grabs the cursor shouldn't break or place anything too
+            actions.clear();
+            GameState::Playing
+        },
+        _ => return
+    };
+
+    // Only fails if the state is already changing this frame
+    let _ = state.set(next);
+}
+
+/// Hide the cursor and keep it in the window, so moving the mouse only looks around
+pub(crate) fn grab_cursor(mut windows: ResMut<Windows>) {
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_cursor_lock_mode(true);
+        window.set_cursor_visibility(false);
+    }
+}
+
+pub(crate) fn release_cursor(mut windows: ResMut<Windows>) {
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_cursor_lock_mode(false);
+        window.set_cursor_visibility(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::core::CorePlugin;
+    use bevy::input::keyboard::KeyboardInput;
+    use bevy::input::mouse::MouseButtonInput;
+    use bevy::input::{ElementState, InputPlugin, InputSystem};
+    use bevy::app::Events;
+    use bevy::window::WindowId;
+    use crate::settings::Settings;
+    use crate::systems::update_actions;
+
+    /// Counts the frames that gameplay systems ran in
+    #[derive(Default)]
+    struct Played(u32);
+
+    fn play(mut played: ResMut<Played>) {
+        played.0 += 1;
+    }
+
+    fn send<T: Send + Sync + 'static>(app: &mut App, event: T) {
+        app.world.get_resource_mut::<Events<T>>().unwrap().send(event);
+        app.update();
+    }
+
+    #[test]
+    fn pausing() {
+        let mut app = App::new();
+        app.add_plugin(CorePlugin)
+            .add_plugin(InputPlugin)
+            .add_event::<WindowFocused>()
+            .insert_resource(Windows::default())
+            .insert_resource(Settings::default())
+            .init_resource::<Input<Action>>()
+            .init_resource::<Played>()
+            .add_state(GameState::Paused)
+            .add_system_to_stage(CoreStage::PreUpdate, update_actions.label("actions").after(InputSystem))
+            .add_system_to_stage(CoreStage::PreUpdate, pause.after("actions"))
+            .add_system_set(SystemSet::on_update(GameState::Playing).with_system(play));
+
+        let state = |app: &App| *app.world.get_resource::<State<GameState>>().unwrap().current();
+        let played = |app: &App| app.world.get_resource::<Played>().unwrap().0;
+        let click = MouseButtonInput { button: MouseButton::Left, state: ElementState::Pressed };
+        let escape = KeyboardInput { scan_code: 1, key_code: Some(KeyCode::Escape), state: ElementState::Pressed };
+        let release_escape = KeyboardInput { state: ElementState::Released, ..escape.clone() };
+
+        // Nothing happens until the window is clicked
+        app.update();
+        assert_eq!(state(&app), GameState::Paused);
+        assert_eq!(played(&app), 0);
+
+        send(&mut app, click.clone());
+        assert_eq!(state(&app), GameState::Playing);
+        // The click isn't used for breaking voxels
+        assert!(!app.world.get_resource::<Input<Action>>().unwrap().just_pressed(Action::Break));
+        app.update();
+        assert!(played(&app) > 0);
+
+        send(&mut app, escape.clone());
+        assert_eq!(state(&app), GameState::Paused);
+        let paused_at = played(&app);
+        send(&mut app, release_escape.clone());
+        app.update();
+        assert_eq!(played(&app), paused_at);
+
+        // Escape resumes too
+        send(&mut app, escape);
+        assert_eq!(state(&app), GameState::Playing);
+        send(&mut app, release_escape);
+
+        send(&mut app, WindowFocused { id: WindowId::primary(), focused: false });
+        assert_eq!(state(&app), GameState::Paused);
+    }
+}
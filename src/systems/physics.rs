@@ -0,0 +1,231 @@
+use bevy::prelude::*;
+
+use crate::components::Player;
+use crate::world::manager::ChunkManager;
+
+/// Downwards acceleration, in world units per second squared
+const GRAVITY: f32 = 28.0;
+
+/// The player never falls faster than this, in world units per second
+const TERMINAL_VELOCITY: f32 = 60.0;
+
+/// Upwards speed at the start of a jump, in world units per second. High enough to jump on top of a voxel.
+pub(crate) const JUMP_SPEED: f32 = 9.0;
+
+/// Frames longer than this are simulated in several steps, so a lag spike doesn't make things jump around
+const MAX_STEP: f32 = 1.0 / 30.0;
+
+/// Make `player` jump if they're standing on something. Returns whether they jumped.
+pub(crate) fn jump(player: &mut Player) -> bool {
+    if !player.grounded {
+        return false
+    }
+
+    player.velocity.y = JUMP_SPEED;
+    player.grounded = false;
+    true
+}
+
+/// Simulate `player` for `delta` seconds, moving them the way their movement mode does
+pub(crate) fn step_body(chunks: &ChunkManager, player: &mut Player, delta: f32) {
+    if player.mode.has_gravity() {
+        player.velocity.y = (player.velocity.y - GRAVITY * delta).max(-TERMINAL_VELOCITY);
+    }
+
+    if !player.mode.collides() {
+        player.grounded = false;
+        player.position += player.velocity * delta;
+        return
+    }
+
+    let sweep = chunks.sweep(&Player::collider(), player.position, player.velocity * delta);
+    player.grounded = sweep.hit[1] && player.velocity.y < 0.0;
+
+    // Stop moving into whatever we ran into
+    if sweep.hit[0] {
+        player.velocity.x = 0.0;
+    }
+    if sweep.hit[1] {
+        player.velocity.y = 0.0;
+    }
+    if sweep.hit[2] {
+        player.velocity.z = 0.0;
+    }
+
+    player.position = sweep.position;
+}
+
+/// Move the player by their velocity, with gravity, colliding with voxels
+pub(crate) fn physics(time: Res<Time>, chunks: Res<ChunkManager>, mut players: Query<&mut Player>) {
+    let steps = (time.delta_seconds() / MAX_STEP).ceil().max(1.0);
+    let delta = time.delta_seconds() / steps;
+
+    for mut player in players.iter_mut() {
+        let mut moved = player.clone();
+        for _ in 0..steps as u32 {
+            step_body(&chunks, &mut moved, delta);
+        }
+
+        // Only write when something changed, so a player standing still doesn't trigger change detection
+        if moved != *player {
+            *player = moved;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::MovementMode;
+    use crate::util::Volume;
+    use crate::world::chunk::{voxel_scale, Chunk, CHUNK_SIZE};
+    use crate::world::coords::{ChunkPos, WorldPos};
+    use crate::world::voxel::Voxel;
+
+    const FRAME: f32 = 1.0 / 60.0;
+
+    /// A chunk with a floor at y = 0
+    fn floor() -> ChunkManager {
+        let mut chunks = ChunkManager::default();
+        chunks.insert(Chunk::new(ChunkPos::new(0, 0, 0), Volume::filled(Voxel::inactive())));
+        for x in 0..16 {
+            for z in 0..16 {
+                chunks.set_voxel(WorldPos::new(x, 0, z), Voxel::active());
+            }
+        }
+        chunks
+    }
+
+    /// Run the player for `seconds`, calling `control` before every frame
+    fn simulate<F: FnMut(&mut Player)>(chunks: &ChunkManager, player: &mut Player, seconds: f32, mut control: F) {
+        for _ in 0..(seconds / FRAME) as u32 {
+            control(player);
+            step_body(chunks, player, FRAME);
+        }
+    }
+
+    /// Height of the player's feet when they're standing on top of voxel layer `y`
+    fn standing_on(y: i32) -> f32 {
+        (y + 1) as f32 * voxel_scale()
+    }
+
+    /// A walking player with their feet at `position`
+    fn at(position: Vec3) -> Player {
+        Player { position, ..Default::default() }
+    }
+
+    #[test]
+    fn falling_and_landing() {
+        let chunks = floor();
+        let mut player = at(Vec3::new(4.5, 10.0, 4.5));
+
+        simulate(&chunks, &mut player, 2.0, |_| ());
+        assert!((player.position.y - standing_on(0)).abs() < 1e-3);
+        assert!(player.grounded);
+        assert_eq!(player.velocity.y, 0.0);
+
+        // Standing still keeps us where we are
+        let position = player.position;
+        simulate(&chunks, &mut player, 1.0, |_| ());
+        assert_eq!(player.position, position);
+        assert!(player.grounded);
+    }
+
+    #[test]
+    fn jumping() {
+        let chunks = floor();
+
+        // Can't jump in mid air
+        let mut player = Player::default();
+        assert!(!jump(&mut player));
+        assert_eq!(player.velocity, Vec3::ZERO);
+
+        let start = Vec3::new(4.5, standing_on(0), 4.5);
+        let mut player = Player { grounded: true, ..at(start) };
+        let mut highest = start.y;
+        assert!(jump(&mut player));
+        for _ in 0..120 {
+            step_body(&chunks, &mut player, FRAME);
+            highest = highest.max(player.position.y);
+        }
+
+        // High enough to get on top of a voxel, and back on the ground afterwards
+        assert!(highest - start.y > voxel_scale());
+        assert!((player.position.y - start.y).abs() < 1e-3);
+        assert!(player.grounded);
+    }
+
+    #[test]
+    fn walking_into_walls() {
+        let mut chunks = floor();
+        for y in 1..4 {
+            chunks.set_voxel(WorldPos::new(8, y, 4), Voxel::active());
+        }
+        // A step that can be jumped on
+        chunks.set_voxel(WorldPos::new(8, 1, 10), Voxel::active());
+
+        let mut player = at(Vec3::new(4.5, standing_on(0), 4.5));
+        simulate(&chunks, &mut player, 3.0, |player| player.velocity.x = 4.0);
+        assert!((player.position.x - (8.0 * voxel_scale() - Player::collider().max.x)).abs() < 1e-3);
+        assert!(player.grounded);
+
+        let mut player = at(Vec3::new(4.5, standing_on(0), 10.5));
+        let walk_and_jump = |player: &mut Player| {
+            player.velocity.x = 4.0;
+            jump(player);
+        };
+        simulate(&chunks, &mut player, 3.0, walk_and_jump);
+        assert!(player.position.x > 9.0 * voxel_scale());
+    }
+
+    #[test]
+    fn lag_spikes() {
+        // A really long frame doesn't let the player fall through the floor either
+        let chunks = floor();
+        let top = (CHUNK_SIZE as f32 - 1.0) * voxel_scale();
+        let mut player = Player { velocity: Vec3::new(0.0, -TERMINAL_VELOCITY, 0.0), ..at(Vec3::new(4.5, top, 4.5)) };
+        step_body(&chunks, &mut player, 5.0);
+        assert!((player.position.y - standing_on(0)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn flying() {
+        let mut chunks = floor();
+        for y in 1..4 {
+            chunks.set_voxel(WorldPos::new(8, y, 4), Voxel::active());
+        }
+        let start = Vec3::new(4.5, standing_on(0) + 1.0, 4.5);
+
+        // Without gravity we stay in the air, but the wall still stops us
+        let mut player = Player { mode: MovementMode::Fly, ..at(start) };
+        simulate(&chunks, &mut player, 3.0, |player| player.velocity.x = 4.0);
+        assert_eq!(player.position.y, start.y);
+        assert!((player.position.x - (8.0 * voxel_scale() - Player::collider().max.x)).abs() < 1e-3);
+
+        // Unless we don't collide at all
+        let mut player = Player { mode: MovementMode::Noclip, ..at(start) };
+        simulate(&chunks, &mut player, 3.0, |player| player.velocity.x = 4.0);
+        assert!(player.position.x > 12.0 * voxel_scale());
+        assert!(!player.grounded);
+    }
+
+    #[test]
+    fn player() {
+        let mut world = World::new();
+        world.insert_resource(floor());
+        world.insert_resource(Time::default());
+        let player = world.spawn().insert(at(Vec3::new(4.5, standing_on(0) + 0.05, 4.5))).id();
+
+        // The player falls onto the floor, and ends up standing there
+        let mut stage = SystemStage::single(physics);
+        for _ in 0..5 {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            world.get_resource_mut::<Time>().unwrap().update();
+            stage.run(&mut world);
+        }
+        let player = world.get::<Player>(player).unwrap();
+        assert!((player.position.y - standing_on(0)).abs() < 1e-3);
+        assert!(player.grounded);
+        assert_eq!(player.velocity, Vec3::ZERO);
+    }
+}
@@ -0,0 +1,214 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use bevy::prelude::*;
+
+use crate::components::PlayerCamera;
+use crate::settings::Settings;
+use crate::systems::ChunkMaterial;
+use crate::world::definitions::VoxelDefinitions;
+
+/// Seconds between checking if a watched file changed
+const WATCH_INTERVAL: f32 = 1.0;
+
+/// Last modification time and size of the file at `path`, `None` if it doesn't exist or can't be read
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Notices when a file changes, by checking when it was last modified every now and then
+pub(crate) struct FileWatcher {
+    path: PathBuf,
+    stamp: Option<(SystemTime, u64)>,
+    timer: Timer
+}
+
+impl FileWatcher {
+    pub(crate) fn new<P: Into<PathBuf>>(path: P) -> Self {
+        let path = path.into();
+        Self {
+            stamp: stamp(&path),
+            path,
+            timer: Timer::from_seconds(WATCH_INTERVAL, true)
+        }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the file changed since the last time this returned true, or since the watcher was made.
+    pub(crate) fn changed(&mut self) -> bool {
+        let stamp = stamp(&self.path);
+        if stamp == self.stamp {
+            return false
+        }
+
+        self.stamp = stamp;
+        true
+    }
+
+    /// Like `changed`, but only actually looks at the file once every `WATCH_INTERVAL` seconds
+    pub(crate) fn poll(&mut self, delta: Duration) -> bool {
+        self.timer.tick(delta).just_finished() && self.changed()
+    }
+}
+
+/// Watches the config file that `Settings` were loaded from
+pub(crate) struct SettingsWatcher(pub(crate) FileWatcher);
+
+/// Watches the file that `VoxelDefinitions` were loaded from
+pub(crate) struct VoxelWatcher(pub(crate) FileWatcher);
+
+/// Load the settings again when the config file changes. Settings that can only be changed by restarting are kept
+/// as they are, and if the new config can't be loaded the old settings are kept too.
+pub(crate) fn reload_settings(
+    time: Res<Time>,
+    mut watcher: ResMut<SettingsWatcher>,
+    mut settings: ResMut<Settings>,
+    mut camera: Query<&mut PerspectiveProjection, With<PlayerCamera>>
+) {
+    if !watcher.0.poll(time.delta()) {
+        return
+    }
+
+    let path = watcher.0.path();
+    let (mut new, warnings) = match Settings::load(path) {
+        Ok(loaded) => loaded,
+        Err(error) => {
+            error!("couldn't reload settings from '{}', keeping the old ones: {}", path.display(), error);
+            return
+        }
+    };
+    for warning in warnings {
+        warn!("{} in '{}'", warning, path.display());
+    }
+
+    // todo: msaa can probably be changed at runtime, but the pipelines would have to be made again
+    if new.graphics.msaa != settings.graphics.msaa {
+        warn!("graphics.msaa only changes after restarting");
+        new.graphics.msaa = settings.graphics.msaa;
+    }
+    if new.world != settings.world {
+        warn!("world settings only change after restarting");
+        new.world = settings.world.clone();
+    }
+
+    if new == *settings {
+        return
+    }
+
+    if new.graphics.fov != settings.graphics.fov {
+        for mut projection in camera.iter_mut() {
+            projection.fov = new.graphics.fov.to_radians();
+        }
+    }
+
+    *settings = new;
+    info!("Reloaded settings from '{}'", path.display());
+}
+
+/// Load the voxel definitions again when their file changes, updating the chunk material. Meshes don't have any
+/// colors in them, so chunks don't have to be remeshed. If the new definitions can't be loaded the old ones are kept.
+pub(crate) fn reload_voxels(
+    time: Res<Time>,
+    mut watcher: ResMut<VoxelWatcher>,
+    mut definitions: ResMut<VoxelDefinitions>,
+    material: Res<ChunkMaterial>,
+    mut materials: ResMut<Assets<StandardMaterial>>
+) {
+    if !watcher.0.poll(time.delta()) {
+        return
+    }
+
+    let path = watcher.0.path();
+    let new = match VoxelDefinitions::load(path) {
+        Ok(new) => new,
+        Err(error) => {
+            error!("couldn't reload voxel definitions from '{}', keeping the old ones: {}", path.display(), error);
+            return
+        }
+    };
+
+    let changed = definitions.changed(&new);
+    if changed.is_empty() {
+        return
+    }
+
+    // todo: every chunk is drawn with the solid material until there are different kinds of voxels
+    if let Some(material) = materials.get_mut(&material.0) {
+        *material = new.solid().material();
+    }
+
+    info!("Reloaded voxel definitions ({} changed)", changed.join(", "));
+    *definitions = new;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::GraphicsSettings;
+    use crate::util::testing::test_file;
+
+    /// Make `watcher` look at its file the next time it's polled
+    fn due(watcher: &mut FileWatcher) {
+        let interval = watcher.timer.duration();
+        watcher.timer.set_elapsed(interval);
+    }
+
+    #[test]
+    fn watching() {
+        let path = test_file("watching", "toml");
+        fs::write(&path, "a").unwrap();
+        let mut watcher = FileWatcher::new(&path);
+        assert!(!watcher.changed());
+
+        // Only looks at the file once the interval is up
+        fs::write(&path, "bb").unwrap();
+        assert!(!watcher.poll(Duration::from_secs_f32(WATCH_INTERVAL / 2.0)));
+        assert!(watcher.poll(Duration::from_secs_f32(WATCH_INTERVAL / 2.0)));
+        assert!(!watcher.changed());
+
+        // Deleting counts as a change too
+        fs::remove_file(&path).unwrap();
+        assert!(watcher.changed());
+    }
+
+    #[test]
+    fn reloading_settings() {
+        let path = test_file("reloading-settings", "toml");
+        fs::write(&path, "[controls]\nmouse_sens = 0.1").unwrap();
+
+        let mut world = World::new();
+        world.insert_resource(Time::default());
+        world.insert_resource(Settings::default());
+        world.insert_resource(SettingsWatcher(FileWatcher::new(&path)));
+        world.spawn().insert_bundle((PlayerCamera::default(), PerspectiveProjection::default()));
+
+        let mut stage = SystemStage::single(reload_settings);
+        let mut reload = |world: &mut World, config: &str| {
+            fs::write(&path, config).unwrap();
+            due(&mut world.get_resource_mut::<SettingsWatcher>().unwrap().0);
+            stage.run(world);
+        };
+
+        reload(&mut world, "[controls]\nmouse_sens = 0.25\n[graphics]\nfov = 70.0\nmsaa = 1");
+        let settings = world.get_resource::<Settings>().unwrap();
+        assert_eq!(settings.controls.mouse_sens, 0.25);
+        assert_eq!(settings.graphics.fov, 70.0);
+        // Needs a restart
+        assert_eq!(settings.graphics.msaa, GraphicsSettings::default().msaa);
+        let fov = world.query::<&PerspectiveProjection>().iter(&world).next().unwrap().fov;
+        assert!((fov - 70f32.to_radians()).abs() < 1e-6);
+
+        // Broken configs don't change anything
+        reload(&mut world, "[controls]\nmouse_sens = \"fast\"");
+        assert_eq!(world.get_resource::<Settings>().unwrap().controls.mouse_sens, 0.25);
+        reload(&mut world, "[controls]\nmouse_sens = -1.0");
+        assert_eq!(world.get_resource::<Settings>().unwrap().controls.mouse_sens, 0.25);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,173 @@
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy::tasks::{IoTaskPool, Task};
+use futures_lite::future;
+
+use crate::components::Player;
+use crate::systems::TimeOfDay;
+use crate::world::chunk::Chunk;
+use crate::world::coords::ChunkPos;
+use crate::world::manager::ChunkManager;
+use crate::world::meta::{WorldError, WorldMeta};
+use crate::world::region::{SaveError, WorldStorage};
+
+/// Seconds between autosaves
+const AUTOSAVE_INTERVAL: f32 = 60.0;
+
+/// Keeps track of when to autosave, and the save that's currently running in the background
+pub(crate) struct Autosave {
+    timer: Timer,
+    task: Option<Task<SaveResult>>
+}
+
+impl Default for Autosave {
+    fn default() -> Self {
+        Self {
+            timer: Timer::from_seconds(AUTOSAVE_INTERVAL, true),
+            task: None
+        }
+    }
+}
+
+/// Saves of changed chunks that are being evicted, see `ChunkManager::evict`
+#[derive(Default)]
+pub(crate) struct EvictionSaves(Vec<Task<ChunkSave>>);
+
+impl EvictionSaves {
+    /// Start saving `to_save` on the IO task pool
+    pub(crate) fn start(&mut self, pool: &IoTaskPool, chunks: &ChunkManager, to_save: Vec<Chunk>) {
+        if to_save.is_empty() {
+            return;
+        }
+        // Chunks are only evicted before they're saved when there's storage to save them to
+        let storage = chunks.storage().expect("evicting changed chunks without storage");
+        self.0.push(pool.spawn(async move { save_chunks(&storage, to_save) }));
+    }
+
+    /// Finish the saves that are done
+    pub(crate) fn poll(&mut self, chunks: &mut ChunkManager) {
+        let mut running = Vec::new();
+        for mut task in self.0.drain(..) {
+            match future::block_on(future::poll_once(&mut task)) {
+                Some(save) => finish_chunk_save(chunks, save, "changed chunks before evicting them"),
+                None => running.push(task)
+            }
+        }
+        self.0 = running;
+    }
+}
+
+struct ChunkSave {
+    chunks: Vec<ChunkPos>,
+    result: Result<usize, SaveError>
+}
+
+fn save_chunks(storage: &WorldStorage, chunks: Vec<Chunk>) -> ChunkSave {
+    ChunkSave {
+        result: storage.save_chunks(&chunks),
+        chunks: chunks.iter().map(|chunk| chunk.position()).collect()
+    }
+}
+
+fn finish_chunk_save(chunks: &mut ChunkManager, save: ChunkSave, what: &str) {
+    match save.result {
+        Ok(_) => {
+            info!("Saved {} {}", save.chunks.len(), what);
+            chunks.finish_saving(&save.chunks, &[]);
+        },
+        Err(error) => {
+            // They're changed again, so they're saved next time
+            error!("Couldn't save {}: {}", what, error);
+            chunks.finish_saving(&save.chunks, &error.chunks);
+        }
+    }
+}
+
+struct SaveResult {
+    chunks: ChunkSave,
+    meta_result: Result<(), WorldError>
+}
+
+/// Write the player's current state and the time of day into `meta`
+fn update_meta(meta: &mut WorldMeta, time: &TimeOfDay, player: &Player) {
+    meta.player = Some(player.clone());
+    meta.time_of_day = time.0;
+}
+
+/// Start saving the changed chunks and `meta` on the IO task pool. Returns `None` if the world isn't saved anywhere.
+fn start_save(pool: &IoTaskPool, chunks: &mut ChunkManager, meta: &WorldMeta) -> Option<Task<SaveResult>> {
+    let storage = chunks.storage()?;
+    let dirty: Vec<Chunk> = chunks.take_dirty();
+    let meta = meta.clone();
+
+    Some(pool.spawn(async move {
+        let chunks = save_chunks(&storage, dirty);
+        let meta_result = meta.save(storage.dir());
+        SaveResult { chunks, meta_result }
+    }))
+}
+
+fn finish_save(chunks: &mut ChunkManager, result: SaveResult) {
+    finish_chunk_save(chunks, result.chunks, "chunks");
+
+    if let Err(error) = result.meta_result {
+        error!("Couldn't save world metadata: {}", error);
+    }
+}
+
+/// Periodically save changed chunks and the player's state in the background
+pub(crate) fn autosave(
+    time: Res<Time>,
+    pool: Res<IoTaskPool>,
+    sky: Res<TimeOfDay>,
+    mut state: ResMut<Autosave>,
+    mut chunks: ResMut<ChunkManager>,
+    mut meta: ResMut<WorldMeta>,
+    player: Query<&Player>
+) {
+    state.timer.tick(time.delta());
+
+    if let Some(task) = &mut state.task {
+        match future::block_on(future::poll_once(task)) {
+            Some(result) => finish_save(&mut chunks, result),
+            // Still saving
+            None => return
+        }
+        state.task = None;
+    }
+
+    if state.timer.just_finished() {
+        update_meta(&mut meta, &sky, player.single());
+        state.task = start_save(&pool, &mut chunks, &meta);
+    }
+}
+
+/// Save everything before the app exits, waiting for any autosave or eviction that's still saving first.
+/// This runs in `CoreStage::Last` so it sees exit events sent during the rest of the frame.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn save_on_exit(
+    mut exit: EventReader<AppExit>,
+    pool: Res<IoTaskPool>,
+    sky: Res<TimeOfDay>,
+    mut state: ResMut<Autosave>,
+    mut evictions: ResMut<EvictionSaves>,
+    mut chunks: ResMut<ChunkManager>,
+    mut meta: ResMut<WorldMeta>,
+    player: Query<&Player>
+) {
+    if exit.iter().next().is_none() {
+        return
+    }
+
+    if let Some(task) = state.task.take() {
+        finish_save(&mut chunks, future::block_on(task));
+    }
+    for task in evictions.0.drain(..) {
+        finish_chunk_save(&mut chunks, future::block_on(task), "changed chunks before evicting them");
+    }
+
+    update_meta(&mut meta, &sky, player.single());
+    if let Some(task) = start_save(&pool, &mut chunks, &meta) {
+        finish_save(&mut chunks, future::block_on(task));
+    }
+}
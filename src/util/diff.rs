@@ -0,0 +1,221 @@
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+use crate::util::{Volume, VolumeIdx};
+
+/// A single item that changed between two versions of a volume
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VolumeChange<T> {
+    pub idx: VolumeIdx,
+    pub old: T,
+    pub new: T
+}
+
+/// Every item that changed between two versions of a volume. Keeps track of the old values too, so it can be undone.
+/// Serialized as a list of changes, in the order that `Volume::iter` goes through their items.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct VolumeDiff<T> {
+    // Sorted by `order`, with at most one change for each index
+    changes: Vec<VolumeChange<T>>
+}
+
+impl<T> Default for VolumeDiff<T> {
+    fn default() -> Self {
+        Self { changes: Vec::new() }
+    }
+}
+
+/// Sort key for changes, which goes through indices in the same order as volumes iterate through them
+fn order(idx: VolumeIdx) -> (usize, usize, usize) {
+    (idx.2, idx.1, idx.0)
+}
+
+/// Changes can be in any order, but the same item can't change more than once.
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for VolumeDiff<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut changes = Vec::<VolumeChange<T>>::deserialize(deserializer)?;
+        changes.sort_by_key(|change| order(change.idx));
+
+        if let Some(pair) = changes.windows(2).find(|pair| pair[0].idx == pair[1].idx) {
+            return Err(de::Error::custom(format_args!("item {:?} changed more than once", pair[0].idx)));
+        }
+
+        Ok(Self { changes })
+    }
+}
+
+impl<T> VolumeDiff<T> {
+    /// Diff that turns `old` into `new`.
+    pub fn between<const X: usize, const Y: usize, const Z: usize>(
+        old: &Volume<T, X, Y, Z>,
+        new: &Volume<T, X, Y, Z>
+    ) -> Self where T: Copy + PartialEq {
+        let changes = old.iter()
+            .filter(|&(idx, &old)| old != new[idx])
+            .map(|(idx, &old)| VolumeChange { idx, old, new: new[idx] })
+            .collect();
+
+        Self { changes }
+    }
+
+    /// Record a change, e.g. as it's happening instead of diffing afterwards.
+    /// Changing the same item more than once keeps the original old value around, and changing it back to that
+    /// drops the change.
+    pub fn record(&mut self, idx: VolumeIdx, old: T, new: T) where T: PartialEq {
+        match self.changes.binary_search_by_key(&order(idx), |change| order(change.idx)) {
+            Ok(n) if self.changes[n].old == new => {
+                self.changes.remove(n);
+            },
+            Ok(n) => self.changes[n].new = new,
+            Err(n) if old != new => self.changes.insert(n, VolumeChange { idx, old, new }),
+            Err(_) => ()
+        }
+    }
+
+    pub fn changes(&self) -> &[VolumeChange<T>] {
+        &self.changes
+    }
+
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Whether every change is within the bounds of a `X` by `Y` by `Z` volume.
+    pub fn fits<const X: usize, const Y: usize, const Z: usize>(&self) -> bool {
+        self.changes.iter().all(|change| change.idx.0 < X && change.idx.1 < Y && change.idx.2 < Z)
+    }
+
+    /// Apply this diff to `volume`, overwriting the changed items with their new values.
+    /// Returns false without changing anything if the diff doesn't fit in the volume.
+    pub fn apply<const X: usize, const Y: usize, const Z: usize>(&self, volume: &mut Volume<T, X, Y, Z>) -> bool
+        where T: Copy {
+        if !self.fits::<X, Y, Z>() {
+            return false
+        }
+
+        for change in &self.changes {
+            volume[change.idx] = change.new;
+        }
+
+        true
+    }
+
+    /// Diff that undoes this one.
+    pub fn inverted(&self) -> Self where T: Copy {
+        let changes = self.changes.iter()
+            .map(|change| VolumeChange { idx: change.idx, old: change.new, new: change.old })
+            .collect();
+
+        Self { changes }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::CubicVolume;
+
+    fn volumes() -> (CubicVolume<u16, 8>, CubicVolume<u16, 8>) {
+        let mut a: CubicVolume<u16, 8> = Volume::filled(0);
+        for (n, idx) in a.iter_indices().enumerate() {
+            a[idx] = (n % 7) as u16;
+        }
+
+        let mut b = a.clone();
+        b[(0, 0, 0)] = 100;
+        b[(7, 7, 7)] = 200;
+        b[(3, 1, 4)] = 300;
+
+        (a, b)
+    }
+
+    #[test]
+    fn round_trip() {
+        let (a, b) = volumes();
+
+        let diff = VolumeDiff::between(&a, &b);
+        assert_eq!(diff.len(), 3);
+
+        let mut patched = a.clone();
+        assert!(diff.apply(&mut patched));
+        assert!(patched == b);
+
+        // And back again
+        assert!(diff.inverted().apply(&mut patched));
+        assert!(patched == a);
+
+        // Nothing changed, nothing to diff
+        assert!(VolumeDiff::between(&a, &a).is_empty());
+    }
+
+    #[test]
+    fn record() {
+        let (a, b) = volumes();
+
+        let mut diff = VolumeDiff::default();
+        diff.record((0, 0, 0), a[(0, 0, 0)], 1);
+        diff.record((0, 0, 0), 1, 100);
+        diff.record((7, 7, 7), a[(7, 7, 7)], 200);
+        diff.record((3, 1, 4), a[(3, 1, 4)], 300);
+
+        // Changing the same item twice only records it once
+        assert_eq!(diff.len(), 3);
+        assert_eq!(diff, VolumeDiff::between(&a, &b));
+
+        let mut patched = a.clone();
+        diff.apply(&mut patched);
+        assert!(patched == b);
+
+        // Changing something back to what it was isn't a change anymore
+        diff.record((3, 1, 4), 300, a[(3, 1, 4)]);
+        diff.record((1, 1, 1), a[(1, 1, 1)], a[(1, 1, 1)]);
+        assert_eq!(diff.len(), 2);
+        assert!(diff.changes().iter().all(|change| change.idx != (3, 1, 4)));
+    }
+
+    #[test]
+    fn out_of_bounds() {
+        let mut diff = VolumeDiff::default();
+        diff.record((1, 1, 1), 0u16, 1);
+        diff.record((8, 0, 0), 0u16, 1);
+
+        let mut volume: CubicVolume<u16, 8> = Volume::filled(0);
+        assert!(!diff.fits::<8, 8, 8>());
+        assert!(!diff.apply(&mut volume));
+
+        // Nothing changed, not even the part that did fit
+        assert_eq!(volume[(1, 1, 1)], 0);
+    }
+
+    #[test]
+    fn serialize() {
+        let (a, b) = volumes();
+        let diff = VolumeDiff::between(&a, &b);
+
+        let bytes = bincode::serialize(&diff).unwrap();
+        let deserialized: VolumeDiff<u16> = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(diff, deserialized);
+
+        let mut patched = a.clone();
+        deserialized.apply(&mut patched);
+        assert!(patched == b);
+
+        // Text formats work too, they can't have indices as keys
+        let value = toml::Value::try_from(&diff).unwrap();
+        assert_eq!(value.as_array().unwrap().len(), 3);
+        assert_eq!(value.try_into::<VolumeDiff<u16>>().unwrap(), diff);
+
+        // Changes are sorted again when they're read, but can't be there twice
+        let change = |idx, new| VolumeChange { idx, old: 0u16, new };
+        let unsorted = bincode::serialize(&vec![change((5, 0, 0), 1), change((1, 0, 0), 2)]).unwrap();
+        let diff: VolumeDiff<u16> = bincode::deserialize(&unsorted).unwrap();
+        assert_eq!(diff.changes(), &[change((1, 0, 0), 2), change((5, 0, 0), 1)]);
+
+        let twice = bincode::serialize(&vec![change((1, 0, 0), 1), change((1, 0, 0), 2)]).unwrap();
+        assert!(bincode::deserialize::<VolumeDiff<u16>>(&twice).is_err());
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::util::{Volume, VolumeIdx};
+
+/// A group of 6-connected items (i.e. connected through faces, not edges or corners), found by flood filling
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ConnectedRegion<P> {
+    /// Number of items in the region
+    pub size: usize,
+    /// Smallest corner of the region's bounding box (inclusive)
+    pub min: P,
+    /// Largest corner of the region's bounding box (inclusive)
+    pub max: P
+}
+
+/// Positions that a `ConnectedRegion` can be made of
+pub trait RegionPoint: Copy {
+    fn component_min(self, other: Self) -> Self;
+    fn component_max(self, other: Self) -> Self;
+}
+
+impl RegionPoint for VolumeIdx {
+    fn component_min(self, other: Self) -> Self {
+        (self.0.min(other.0), self.1.min(other.1), self.2.min(other.2))
+    }
+
+    fn component_max(self, other: Self) -> Self {
+        (self.0.max(other.0), self.1.max(other.1), self.2.max(other.2))
+    }
+}
+
+impl RegionPoint for IVec3 {
+    fn component_min(self, other: Self) -> Self {
+        self.min(other)
+    }
+
+    fn component_max(self, other: Self) -> Self {
+        self.max(other)
+    }
+}
+
+impl<P: RegionPoint> ConnectedRegion<P> {
+    /// Empty region with its bounding box at `pos`
+    pub fn new(pos: P) -> Self {
+        Self { size: 0, min: pos, max: pos }
+    }
+
+    pub fn add(&mut self, pos: P) {
+        self.size += 1;
+        self.min = self.min.component_min(pos);
+        self.max = self.max.component_max(pos);
+    }
+}
+
+/// Every connected region of a volume, along with which region each item belongs to
+pub struct RegionLabels<const X: usize, const Y: usize, const Z: usize> {
+    /// The region of each item, as an index into `regions` plus one. Items that aren't in any region are 0.
+    pub labels: Volume<u32, X, Y, Z>,
+    pub regions: Vec<ConnectedRegion<VolumeIdx>>
+}
+
+impl<const X: usize, const Y: usize, const Z: usize> RegionLabels<X, Y, Z> {
+    /// The region the item at `idx` belongs to, if any
+    pub fn region_of(&self, idx: VolumeIdx) -> Option<&ConnectedRegion<VolumeIdx>> {
+        match *self.labels.get(idx)? {
+            0 => None,
+            label => self.regions.get(label as usize - 1)
+        }
+    }
+}
+
+/// The (up to) 6 indices sharing a face with `idx` in a volume with dimensions `dims`
+fn face_neighbors(idx: VolumeIdx, dims: VolumeIdx) -> impl Iterator<Item = VolumeIdx> {
+    let (x, y, z) = idx;
+    [
+        (x + 1 < dims.0).then(|| (x + 1, y, z)),
+        x.checked_sub(1).map(|x| (x, y, z)),
+        (y + 1 < dims.1).then(|| (x, y + 1, z)),
+        y.checked_sub(1).map(|y| (x, y, z)),
+        (z + 1 < dims.2).then(|| (x, y, z + 1)),
+        z.checked_sub(1).map(|z| (x, y, z)),
+    ].into_iter().flatten()
+}
+
+impl<T, const X: usize, const Y: usize, const Z: usize> Volume<T, X, Y, Z> {
+    /// Indices of all the items that are connected to `start` through items matching `predicate`, including `start`
+    /// itself. Returns nothing if `start` is out of bounds or doesn't match.
+    pub fn flood<F: FnMut(&T) -> bool>(&self, start: VolumeIdx, mut predicate: F) -> Vec<VolumeIdx> {
+        let mut visited: Volume<bool, X, Y, Z> = Volume::filled(false);
+        let mut out = Vec::new();
+
+        match self.get(start) {
+            Some(item) if predicate(item) => (),
+            _ => return out
+        }
+
+        let mut queue = VecDeque::from([start]);
+        visited[start] = true;
+
+        while let Some(idx) = queue.pop_front() {
+            out.push(idx);
+
+            for neighbor in face_neighbors(idx, Self::DIMS) {
+                if !visited[neighbor] && predicate(&self[neighbor]) {
+                    visited[neighbor] = true;
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Set every item connected to `start` through items matching `predicate` to `value`.
+    /// Returns the region that was filled, if `start` matched.
+    pub fn flood_fill<F: FnMut(&T) -> bool>(
+        &mut self,
+        start: VolumeIdx,
+        predicate: F,
+        value: T
+    ) -> Option<ConnectedRegion<VolumeIdx>> where T: Copy {
+        let filled = self.flood(start, predicate);
+        let mut region = ConnectedRegion::new(*filled.first()?);
+
+        for idx in filled {
+            self[idx] = value;
+            region.add(idx);
+        }
+
+        Some(region)
+    }
+
+    /// Find every 6-connected region of items matching `predicate`.
+    pub fn label_regions<F: FnMut(&T) -> bool>(&self, mut predicate: F) -> RegionLabels<X, Y, Z> {
+        let mut labels: Volume<u32, X, Y, Z> = Volume::filled(0);
+        let mut regions = Vec::new();
+        let mut queue = VecDeque::new();
+
+        for start in self.iter_indices() {
+            if labels[start] != 0 || !predicate(&self[start]) {
+                continue;
+            }
+
+            let label = regions.len() as u32 + 1;
+            let mut region = ConnectedRegion::new(start);
+
+            labels[start] = label;
+            queue.push_back(start);
+
+            while let Some(idx) = queue.pop_front() {
+                region.add(idx);
+
+                for neighbor in face_neighbors(idx, Self::DIMS) {
+                    if labels[neighbor] == 0 && predicate(&self[neighbor]) {
+                        labels[neighbor] = label;
+                        queue.push_back(neighbor);
+                    }
+                }
+            }
+
+            regions.push(region);
+        }
+
+        RegionLabels { labels, regions }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::CubicVolume;
+
+    #[test]
+    fn flood() {
+        let mut volume: CubicVolume<u8, 8> = Volume::filled(0u8);
+
+        // A hollow box of 1s, with a single 0 inside
+        for x in 2..5 {
+            for y in 2..5 {
+                for z in 2..5 {
+                    volume[(x, y, z)] = 1;
+                }
+            }
+        }
+        volume[(3, 3, 3)] = 0;
+
+        // The air inside the box isn't connected to the air outside
+        assert_eq!(volume.flood((3, 3, 3), |&v| v == 0), vec![(3, 3, 3)]);
+        assert_eq!(volume.flood((0, 0, 0), |&v| v == 0).len(), 8 * 8 * 8 - 27);
+
+        // Starting on something that doesn't match, or out of bounds, finds nothing
+        assert!(volume.flood((2, 2, 2), |&v| v == 0).is_empty());
+        assert!(volume.flood((8, 0, 0), |&v| v == 0).is_empty());
+
+        // Fill the box (but not the hole) with 2s
+        let region = volume.flood_fill((4, 4, 4), |&v| v == 1, 2).unwrap();
+        assert_eq!(region, ConnectedRegion { size: 26, min: (2, 2, 2), max: (4, 4, 4) });
+        assert_eq!(volume.iter().filter(|(_, &v)| v == 2).count(), 26);
+        assert_eq!(volume[(3, 3, 3)], 0);
+    }
+
+    #[test]
+    fn regions() {
+        let mut volume: Volume<bool, 8, 4, 4> = Volume::filled(false);
+
+        // A line along X
+        for x in 0..5 {
+            volume[(x, 0, 0)] = true;
+        }
+
+        // A single item that only touches the line by its edge, so it's a separate region
+        volume[(2, 1, 1)] = true;
+
+        // An L shape in the far corner
+        volume[(7, 3, 3)] = true;
+        volume[(7, 2, 3)] = true;
+        volume[(6, 2, 3)] = true;
+
+        let labels = volume.label_regions(|&v| v);
+        assert_eq!(labels.regions, vec![
+            ConnectedRegion { size: 5, min: (0, 0, 0), max: (4, 0, 0) },
+            ConnectedRegion { size: 1, min: (2, 1, 1), max: (2, 1, 1) },
+            ConnectedRegion { size: 3, min: (6, 2, 3), max: (7, 3, 3) },
+        ]);
+
+        assert_eq!(labels.labels[(3, 0, 0)], 1);
+        assert_eq!(labels.labels[(2, 1, 1)], 2);
+        assert_eq!(labels.region_of((6, 2, 3)).map(|r| r.size), Some(3));
+        assert_eq!(labels.region_of((5, 0, 0)), None);
+    }
+}
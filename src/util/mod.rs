@@ -1,5 +1,14 @@
 mod volume;
 mod consts;
+mod orientation;
+mod flood;
+// Nothing diffs volumes outside of tests yet
+#[cfg(test)]
+pub(crate) mod diff;
+#[cfg(test)]
+pub(crate) mod testing;
 
 pub(crate) use volume::*;
-pub(crate) use consts::*;
\ No newline at end of file
+pub(crate) use consts::*;
+pub(crate) use orientation::*;
+pub(crate) use flood::*;
\ No newline at end of file
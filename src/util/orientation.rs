@@ -0,0 +1,422 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::util::{CubicVolume, Volume, VolumeIdx};
+
+/// One of the three axes of a volume or the world
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Axis {
+    X,
+    Y,
+    Z
+}
+
+impl Axis {
+    pub const ALL: [Axis; 3] = [Axis::X, Axis::Y, Axis::Z];
+
+    #[inline]
+    pub fn index(self) -> usize {
+        match self {
+            Self::X => 0,
+            Self::Y => 1,
+            Self::Z => 2
+        }
+    }
+
+    #[inline]
+    pub fn from_index(idx: usize) -> Self {
+        Self::ALL[idx]
+    }
+}
+
+/// An axis-aligned rotation and/or mirror. Every orientation maps each axis onto some (possibly negated) axis,
+/// so there are 48 of them in total: the 24 rotations of a cube, and those 24 again mirrored.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Orientation {
+    // Component i of a transformed vector is component `source[i]` of the original vector...
+    source: [Axis; 3],
+    // ...negated if `negate[i]` is set.
+    negate: [bool; 3]
+}
+
+impl Default for Orientation {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Orientation {
+    pub const IDENTITY: Self = Self {
+        source: [Axis::X, Axis::Y, Axis::Z],
+        negate: [false; 3]
+    };
+
+    /// Counterclockwise rotation around `axis` (looking from the positive end of the axis towards the origin),
+    /// in steps of 90 degrees. Negative turns rotate clockwise.
+    pub fn rotation(axis: Axis, quarter_turns: i32) -> Self {
+        let quarter = match axis {
+            // y' = -z, z' = y
+            Axis::X => Self { source: [Axis::X, Axis::Z, Axis::Y], negate: [false, true, false] },
+            // x' = z, z' = -x
+            Axis::Y => Self { source: [Axis::Z, Axis::Y, Axis::X], negate: [false, false, true] },
+            // x' = -y, y' = x
+            Axis::Z => Self { source: [Axis::Y, Axis::X, Axis::Z], negate: [true, false, false] },
+        };
+
+        let mut out = Self::IDENTITY;
+        for _ in 0..quarter_turns.rem_euclid(4) {
+            out = out.then(quarter);
+        }
+
+        out
+    }
+
+    /// Mirror along `axis`, i.e. negate that component.
+    pub fn mirror(axis: Axis) -> Self {
+        let mut out = Self::IDENTITY;
+        out.negate[axis.index()] = true;
+        out
+    }
+
+    /// All 24 rotations (no mirrors), starting with the identity.
+    pub fn rotations() -> [Self; 24] {
+        let mut out = [Self::IDENTITY; 24];
+        let mut n = 0;
+
+        // Point +Y in each of the 6 directions, and then spin around that direction 4 times.
+        let up = [
+            Self::IDENTITY,
+            Self::rotation(Axis::X, 1),
+            Self::rotation(Axis::X, 2),
+            Self::rotation(Axis::X, 3),
+            Self::rotation(Axis::Z, 1),
+            Self::rotation(Axis::Z, 3),
+        ];
+
+        for base in up {
+            for turns in 0..4 {
+                out[n] = Self::rotation(Axis::Y, turns).then(base);
+                n += 1;
+            }
+        }
+
+        out
+    }
+
+    /// All 48 orientations, the 24 rotations followed by their mirrored versions.
+    pub fn all() -> [Self; 48] {
+        let mut out = [Self::IDENTITY; 48];
+        for (i, rot) in Self::rotations().into_iter().enumerate() {
+            out[i] = rot;
+            out[i + 24] = rot.then(Self::mirror(Axis::X));
+        }
+
+        out
+    }
+
+    /// Orientation that applies `self` first and `next` afterwards.
+    pub fn then(self, next: Self) -> Self {
+        let mut out = Self::IDENTITY;
+        for i in 0..3 {
+            let via = next.source[i].index();
+            out.source[i] = self.source[via];
+            out.negate[i] = next.negate[i] ^ self.negate[via];
+        }
+
+        out
+    }
+
+    /// Orientation that undoes `self`.
+    pub fn inverse(self) -> Self {
+        let mut out = Self::IDENTITY;
+        for i in 0..3 {
+            let src = self.source[i].index();
+            out.source[src] = Axis::from_index(i);
+            out.negate[src] = self.negate[i];
+        }
+
+        out
+    }
+
+    /// Whether this is a proper rotation, as opposed to a mirrored one.
+    pub fn is_rotation(self) -> bool {
+        // Every swap of two axes flips handedness, and so does every negated axis.
+        let swaps = match self.source {
+            [Axis::X, Axis::Y, Axis::Z] | [Axis::Y, Axis::Z, Axis::X] | [Axis::Z, Axis::X, Axis::Y] => 0,
+            _ => 1
+        };
+        let negations = self.negate.iter().filter(|&&n| n).count();
+
+        (swaps + negations) % 2 == 0
+    }
+
+    /// The axis that `axis` ends up along after this orientation is applied (ignoring direction).
+    pub fn apply_axis(self, axis: Axis) -> Axis {
+        let i = self.source.iter().position(|&src| src == axis).unwrap();
+        Axis::from_index(i)
+    }
+
+    pub fn apply_ivec(self, v: IVec3) -> IVec3 {
+        let v = v.to_array();
+        let mut out = [0; 3];
+        for i in 0..3 {
+            let component = v[self.source[i].index()];
+            out[i] = if self.negate[i] { -component } else { component };
+        }
+
+        IVec3::from(out)
+    }
+
+    pub fn apply_vec(self, v: Vec3) -> Vec3 {
+        let v = v.to_array();
+        let mut out = [0.0; 3];
+        for i in 0..3 {
+            let component = v[self.source[i].index()];
+            out[i] = if self.negate[i] { -component } else { component };
+        }
+
+        Vec3::from(out)
+    }
+
+    /// Dimensions of a volume with dimensions `dims` after being transformed.
+    pub fn apply_dims(self, dims: VolumeIdx) -> VolumeIdx {
+        let dims = [dims.0, dims.1, dims.2];
+        (dims[self.source[0].index()], dims[self.source[1].index()], dims[self.source[2].index()])
+    }
+
+    /// Where `idx` in a volume with dimensions `dims` ends up after the volume is transformed.
+    /// The volume is transformed in place around its center, so the result is always in bounds of `apply_dims(dims)`.
+    pub fn apply_idx(self, idx: VolumeIdx, dims: VolumeIdx) -> VolumeIdx {
+        let idx = [idx.0, idx.1, idx.2];
+        let dims = [dims.0, dims.1, dims.2];
+        let mut out = [0; 3];
+
+        for (i, component) in out.iter_mut().enumerate() {
+            let src = self.source[i].index();
+            *component = if self.negate[i] { dims[src] - 1 - idx[src] } else { idx[src] };
+        }
+
+        (out[0], out[1], out[2])
+    }
+}
+
+/// Data that has a direction of its own (like a log's axis), and needs to follow along when it gets rotated or mirrored.
+pub trait Orientable {
+    fn oriented(self, orientation: Orientation) -> Self;
+}
+
+impl Orientable for Axis {
+    fn oriented(self, orientation: Orientation) -> Self {
+        orientation.apply_axis(self)
+    }
+}
+
+impl<T: Orientable> Orientable for Option<T> {
+    fn oriented(self, orientation: Orientation) -> Self {
+        self.map(|item| item.oriented(orientation))
+    }
+}
+
+impl<T, const X: usize, const Y: usize, const Z: usize> Volume<T, X, Y, Z> {
+    /// Iterate over the items in this volume along with where they end up after rotating and/or mirroring the volume
+    /// around its center. The new indices are within the bounds of `orientation.apply_dims(self.dims())`.
+    pub fn iter_transformed(&self, orientation: Orientation) -> impl Iterator<Item = (VolumeIdx, &T)> {
+        self.iter().map(move |(idx, item)| (orientation.apply_idx(idx, Self::DIMS), item))
+    }
+
+    /// Rotate and/or mirror the positions of the items in this volume around its center.
+    /// The items themselves are left as they are, use `try_reoriented` if they have an orientation of their own.
+    /// Returns `None` if the transformed volume doesn't have the dimensions `TX`, `TY` and `TZ`.
+    pub fn try_transformed<const TX: usize, const TY: usize, const TZ: usize>(
+        &self,
+        orientation: Orientation
+    ) -> Option<Volume<T, TX, TY, TZ>> where T: Copy {
+        if orientation.apply_dims(Self::DIMS) != (TX, TY, TZ) {
+            return None
+        }
+
+        let mut out = Volume::filled(*self.iter().next()?.1);
+        for (idx, &item) in self.iter_transformed(orientation) {
+            out[idx] = item;
+        }
+
+        Some(out)
+    }
+
+    /// Like `try_transformed`, but also orients every item in the volume.
+    pub fn try_reoriented<const TX: usize, const TY: usize, const TZ: usize>(
+        &self,
+        orientation: Orientation
+    ) -> Option<Volume<T, TX, TY, TZ>> where T: Copy + Orientable {
+        if orientation.apply_dims(Self::DIMS) != (TX, TY, TZ) {
+            return None
+        }
+
+        let mut out = Volume::filled(*self.iter().next()?.1);
+        for (idx, &item) in self.iter_transformed(orientation) {
+            out[idx] = item.oriented(orientation);
+        }
+
+        Some(out)
+    }
+}
+
+impl<T, const SIZE: usize> CubicVolume<T, SIZE> {
+    /// Rotate and/or mirror the positions of the items in this volume around its center.
+    /// The items themselves are left as they are, use `reoriented` if they have an orientation of their own.
+    pub fn transformed(&self, orientation: Orientation) -> Self where T: Copy {
+        // A cube stays a cube no matter how it's transformed, so this only fails if the volume is empty.
+        self.try_transformed(orientation).unwrap()
+    }
+
+    /// Like `transformed`, but also orients every item in the volume.
+    pub fn reoriented(&self, orientation: Orientation) -> Self where T: Copy + Orientable {
+        self.try_reoriented(orientation).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quarter_turns() {
+        for axis in Axis::ALL {
+            let quarter = Orientation::rotation(axis, 1);
+
+            // Four quarter turns get us back to where we started
+            let mut full = Orientation::IDENTITY;
+            for _ in 0..4 {
+                full = full.then(quarter);
+            }
+            assert_eq!(full, Orientation::IDENTITY);
+
+            // Turning backwards is the same as turning the other way around
+            assert_eq!(Orientation::rotation(axis, -1), Orientation::rotation(axis, 3));
+            assert_eq!(Orientation::rotation(axis, -1), quarter.inverse());
+        }
+
+        // Right handed, so +X goes to -Z around Y, +Y goes to +Z around X, and +X goes to +Y around Z
+        assert_eq!(Orientation::rotation(Axis::Y, 1).apply_ivec(IVec3::X), -IVec3::Z);
+        assert_eq!(Orientation::rotation(Axis::X, 1).apply_ivec(IVec3::Y), IVec3::Z);
+        assert_eq!(Orientation::rotation(Axis::Z, 1).apply_ivec(IVec3::X), IVec3::Y);
+    }
+
+    #[test]
+    fn group() {
+        let rotations = Orientation::rotations();
+        let all = Orientation::all();
+
+        // Everything is unique
+        for (i, a) in all.iter().enumerate() {
+            for b in &all[i + 1..] {
+                assert_ne!(a, b);
+            }
+        }
+
+        assert!(rotations.iter().all(|r| r.is_rotation()));
+        assert!(all[24..].iter().all(|r| !r.is_rotation()));
+
+        let v = IVec3::new(1, 2, 3);
+        for &a in &all {
+            // Inverses undo each other, in either order
+            assert_eq!(a.then(a.inverse()), Orientation::IDENTITY);
+            assert_eq!(a.inverse().then(a), Orientation::IDENTITY);
+
+            for &b in &all {
+                let ab = a.then(b);
+
+                // Composition is the same as applying one after the other
+                assert_eq!(ab.apply_ivec(v), b.apply_ivec(a.apply_ivec(v)));
+
+                // Rotations are closed under composition, and mirroring twice is a rotation
+                assert_eq!(ab.is_rotation(), a.is_rotation() == b.is_rotation());
+                assert!(all.contains(&ab));
+            }
+        }
+
+        for axis in Axis::ALL {
+            let mirror = Orientation::mirror(axis);
+            assert_eq!(mirror.then(mirror), Orientation::IDENTITY);
+            assert!(!mirror.is_rotation());
+        }
+    }
+
+    #[test]
+    fn volume_transforms() {
+        let mut volume: CubicVolume<u64, 4> = Volume::filled(0u64);
+        for (n, idx) in volume.iter_indices().enumerate() {
+            volume[idx] = n as u64;
+        }
+
+        for &a in &Orientation::all() {
+            let transformed = volume.transformed(a);
+
+            // Undoing the transform gets us the original volume back
+            let undone = transformed.transformed(a.inverse());
+            assert!(undone.iter().all(|(idx, &v)| volume[idx] == v));
+
+            for &b in &Orientation::all() {
+                // Transforming twice is the same as transforming once with the composed orientation
+                let twice = transformed.transformed(b);
+                let composed = volume.transformed(a.then(b));
+                assert!(twice.iter().all(|(idx, &v)| composed[idx] == v));
+            }
+        }
+    }
+
+    #[test]
+    fn non_cubic_transforms() {
+        let mut volume: Volume<u64, 2, 3, 4> = Volume::filled(0u64);
+        for (n, idx) in volume.iter_indices().enumerate() {
+            volume[idx] = n as u64;
+        }
+
+        let quarter = Orientation::rotation(Axis::Y, 1);
+        assert_eq!(quarter.apply_dims(volume.dims()), (4, 3, 2));
+
+        // Asking for the wrong dimensions doesn't work
+        assert!(volume.try_transformed::<2, 3, 4>(quarter).is_none());
+
+        let rotated: Volume<u64, 4, 3, 2> = volume.try_transformed(quarter).unwrap();
+        let back: Volume<u64, 2, 3, 4> = rotated.try_transformed(quarter.inverse()).unwrap();
+        assert!(back.iter().all(|(idx, &v)| volume[idx] == v));
+
+        // Half a turn twice around different axes is the same as half a turn around the third axis
+        let half_x: Volume<u64, 2, 3, 4> = volume.try_transformed(Orientation::rotation(Axis::X, 2)).unwrap();
+        let half_xy: Volume<u64, 2, 3, 4> = half_x.try_transformed(Orientation::rotation(Axis::Y, 2)).unwrap();
+        let half_z: Volume<u64, 2, 3, 4> = volume.try_transformed(Orientation::rotation(Axis::Z, 2)).unwrap();
+        assert!(half_xy.iter().all(|(idx, &v)| half_z[idx] == v));
+
+        // Mirrors never change the dimensions
+        for axis in Axis::ALL {
+            let mirrored: Volume<u64, 2, 3, 4> = volume.try_transformed(Orientation::mirror(axis)).unwrap();
+            assert_ne!(mirrored[(0, 0, 0)], volume[(0, 0, 0)]);
+        }
+    }
+
+    #[test]
+    fn orientable_items() {
+        // A row of items along X that point along X
+        let mut volume: CubicVolume<Option<Axis>, 4> = Volume::filled(None);
+        for x in 0..4 {
+            volume[(x, 1, 2)] = Some(Axis::X);
+        }
+
+        for &orientation in &Orientation::all() {
+            let reoriented = volume.reoriented(orientation);
+
+            // Wherever the row ended up, every item should still point along the row
+            let row_axis = orientation.apply_axis(Axis::X);
+            for (idx, item) in reoriented.iter() {
+                if let Some(axis) = item {
+                    assert_eq!(*axis, row_axis);
+
+                    let mut next = [idx.0, idx.1, idx.2];
+                    next[row_axis.index()] = (next[row_axis.index()] + 1) % 4;
+                    assert_eq!(reoriented[(next[0], next[1], next[2])], Some(row_axis));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,16 @@
+use std::fs;
+use std::path::PathBuf;
+
+/// Empty directory for a test to put files in, unique to the test and this test run
+pub(crate) fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("svep-test-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+/// Path for a test to write a file with `extension` to, with nothing there yet
+pub(crate) fn test_file(name: &str, extension: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("svep-test-{}-{}.{}", name, std::process::id(), extension));
+    let _ = fs::remove_file(&path);
+    path
+}
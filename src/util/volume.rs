@@ -2,23 +2,32 @@
 
 use std::ops;
 use std::fmt;
+use std::marker::PhantomData;
 
-/// 3 Dimensional volume of data
-pub struct Volume<T: Sized, const SIZE: usize>([[[T; SIZE]; SIZE]; SIZE]);
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde::ser::SerializeSeq;
+
+/// 3 Dimensional volume of data, `X` by `Y` by `Z` items large. The items are kept on the heap, since large volumes
+/// (like the volume of a 64 voxel chunk) don't fit on the stack.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Volume<T: Sized, const X: usize, const Y: usize, const Z: usize>(Box<[[[T; Z]; Y]; X]>);
+
+/// Volume that's equally large in all dimensions, like the volume of a chunk
+pub type CubicVolume<T, const SIZE: usize> = Volume<T, SIZE, SIZE, SIZE>;
 
 /// Iterator over a 3D volume
-pub struct VolumeIterator<'volume, T: Sized, const SIZE: usize> {
-    vol: &'volume Volume<T, SIZE>,
+pub struct VolumeIterator<'volume, T: Sized, const X: usize, const Y: usize, const Z: usize> {
+    vol: &'volume Volume<T, X, Y, Z>,
     idx: VolumeIdx
 }
 
 /// Iterator over the indices in a volume. Can be used instead of mutable iterators.
-pub struct VolumeIndexIterator<const SIZE: usize>(VolumeIdx);
+pub struct VolumeIndexIterator<const X: usize, const Y: usize, const Z: usize>(VolumeIdx);
 
 /// This type may be used to index a Volume
 pub type VolumeIdx = (usize, usize, usize);
 
-impl<T, const SIZE: usize> ops::Index<VolumeIdx> for Volume<T, SIZE> {
+impl<T, const X: usize, const Y: usize, const Z: usize> ops::Index<VolumeIdx> for Volume<T, X, Y, Z> {
     type Output = T;
 
     #[inline]
@@ -27,101 +36,175 @@ impl<T, const SIZE: usize> ops::Index<VolumeIdx> for Volume<T, SIZE> {
     }
 }
 
-impl<T, const SIZE: usize> ops::IndexMut<VolumeIdx> for Volume<T, SIZE> {
+impl<T, const X: usize, const Y: usize, const Z: usize> ops::IndexMut<VolumeIdx> for Volume<T, X, Y, Z> {
     #[inline]
     fn index_mut(&mut self, index: VolumeIdx) -> &mut Self::Output {
         &mut self.0[index.0][index.1][index.2]
     }
 }
 
-impl<T, const SIZE: usize> fmt::Debug for Volume<T, SIZE> {
+impl<T, const X: usize, const Y: usize, const Z: usize> fmt::Debug for Volume<T, X, Y, Z> {
     fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(formatter, "Volume<{0}x{0}x{0}>", SIZE)
+        write!(formatter, "Volume<{}x{}x{}>", X, Y, Z)
+    }
+}
+
+impl<T, const X: usize, const Y: usize, const Z: usize> From<[[[T; Z]; Y]; X]> for Volume<T, X, Y, Z> {
+    fn from(arr: [[[T; Z]; Y]; X]) -> Self {
+        Self { 0: Box::new(arr) }
     }
 }
 
-impl<T, const SIZE: usize> From<[[[T; SIZE]; SIZE]; SIZE]> for Volume<T, SIZE> {
-    fn from(arr: [[[T; SIZE]; SIZE]; SIZE]) -> Self {
-        Self { 0: arr }
+impl<T, const X: usize, const Y: usize, const Z: usize> From<Volume<T, X, Y, Z>> for [[[T; Z]; Y]; X] {
+    fn from(vol: Volume<T, X, Y, Z>) -> Self {
+        *vol.0
     }
 }
 
-impl<T, const SIZE: usize> From<Volume<T, SIZE>> for [[[T; SIZE]; SIZE]; SIZE] {
-    fn from(vol: Volume<T, SIZE>) -> Self {
-        vol.0
+/// Volumes are serialized as a flat sequence of their items, in the same order as `iter` goes through them.
+impl<T: Serialize, const X: usize, const Y: usize, const Z: usize> Serialize for Volume<T, X, Y, Z> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(X * Y * Z))?;
+        for (_, item) in self.iter() {
+            seq.serialize_element(item)?;
+        }
+
+        seq.end()
     }
 }
 
-impl<T, const SIZE: usize> Volume<T, SIZE> {
+impl<'de, T, const X: usize, const Y: usize, const Z: usize> Deserialize<'de> for Volume<T, X, Y, Z>
+    where T: Deserialize<'de> + Copy {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct VolumeVisitor<T, const X: usize, const Y: usize, const Z: usize>(PhantomData<T>);
+
+        impl<'de, T, const X: usize, const Y: usize, const Z: usize> de::Visitor<'de> for VolumeVisitor<T, X, Y, Z>
+            where T: Deserialize<'de> + Copy {
+            type Value = Volume<T, X, Y, Z>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                write!(formatter, "a sequence of {} items", X * Y * Z)
+            }
+
+            fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let len = X * Y * Z;
+                let first: T = match seq.next_element()? {
+                    Some(item) => item,
+                    None if len == 0 => return Err(de::Error::custom("can't deserialize empty volumes")),
+                    None => return Err(de::Error::invalid_length(0, &self))
+                };
+
+                let mut volume = Volume::filled(first);
+                for (n, idx) in volume.iter_indices().enumerate().skip(1) {
+                    volume[idx] = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(n, &self))?;
+                }
+
+                if seq.next_element::<de::IgnoredAny>()?.is_some() {
+                    return Err(de::Error::invalid_length(len + 1, &self));
+                }
+
+                Ok(volume)
+            }
+        }
+
+        deserializer.deserialize_seq(VolumeVisitor(PhantomData))
+    }
+}
+
+impl<T, const X: usize, const Y: usize, const Z: usize> Volume<T, X, Y, Z> {
+    /// Size of this volume along each axis
+    pub const DIMS: VolumeIdx = (X, Y, Z);
+
     pub fn filled(item: T) -> Self where T: Copy {
-        Self { 0: [[[item; SIZE]; SIZE]; SIZE] }
+        // Built straight on the heap, going through `Box::new` would put the whole volume on the stack first
+        let items = vec![[[item; Z]; Y]; X].into_boxed_slice();
+        match items.try_into() {
+            Ok(items) => Self { 0: items },
+            Err(_) => unreachable!("the vec has exactly X items")
+        }
     }
 
-    pub fn iter(&self) -> VolumeIterator<T, SIZE> {
+    pub fn dims(&self) -> VolumeIdx {
+        Self::DIMS
+    }
+
+    pub fn contains(&self, idx: VolumeIdx) -> bool {
+        idx.0 < X && idx.1 < Y && idx.2 < Z
+    }
+
+    pub fn iter(&self) -> VolumeIterator<T, X, Y, Z> {
         VolumeIterator {
             vol: self,
             idx: (0, 0, 0)
         }
     }
 
-    pub fn iter_indices(&self) -> VolumeIndexIterator<SIZE> {
+    pub fn iter_indices(&self) -> VolumeIndexIterator<X, Y, Z> {
         VolumeIndexIterator { 0: (0, 0, 0) }
     }
     
     pub fn get(&self, idx: VolumeIdx) -> Option<&T> {
-        if idx.0 >= SIZE || idx.1 >= SIZE || idx.2 >= SIZE {
+        if self.contains(idx) {
+            Some(&self[idx])
+        } else {
             None
+        }
+    }
+
+    pub fn get_mut(&mut self, idx: VolumeIdx) -> Option<&mut T> {
+        if self.contains(idx) {
+            Some(&mut self[idx])
         } else {
-            Some(&self[idx])
+            None
         }
     }
 }
 
-impl<'volume, T, const SIZE: usize> Iterator for VolumeIterator<'volume, T, SIZE> {
+impl<'volume, T, const X: usize, const Y: usize, const Z: usize> Iterator for VolumeIterator<'volume, T, X, Y, Z> {
     type Item = (VolumeIdx, &'volume T);
     fn next(&mut self) -> Option<Self::Item> {
+        if X == 0 || Y == 0 || self.idx.2 >= Z {
+            return None
+        }
+
         let item = &self.vol[self.idx];
         let item_idx = self.idx;
 
         self.idx.0 += 1;
-        if self.idx.0 >= SIZE {
+        if self.idx.0 >= X {
             self.idx.0 = 0;
             self.idx.1 += 1;
         }
 
-        if self.idx.1 >= SIZE {
+        if self.idx.1 >= Y {
             self.idx.1 = 0;
             self.idx.2 += 1;
         }
 
-        if self.idx.2 >= SIZE {
-            return None
-        }
-
         Some((item_idx, item))
     }
 }
 
-impl<const SIZE: usize> Iterator for VolumeIndexIterator<SIZE> {
+impl<const X: usize, const Y: usize, const Z: usize> Iterator for VolumeIndexIterator<X, Y, Z> {
     type Item = VolumeIdx;
     fn next(&mut self) ->  Option<Self::Item> {
+        if X == 0 || Y == 0 || self.0.2 >= Z {
+            return None
+        }
+
         let index_before = self.0;
 
         self.0.0 += 1;
-        if self.0.0 >= SIZE {
+        if self.0.0 >= X {
             self.0.0 = 0;
             self.0.1 += 1;
         }
 
-        if self.0.1 >= SIZE {
+        if self.0.1 >= Y {
             self.0.1 = 0;
             self.0.2 += 1;
         }
 
-        if self.0.2 >= SIZE {
-            return None
-        }
-
         Some(index_before)
     }
 }
@@ -133,7 +216,7 @@ mod tests {
     #[test]
     fn fill() {
         // Fill a volume up with zeroes
-        let mut volume: Volume<u64, 32> = Volume::filled(0u64);
+        let mut volume: CubicVolume<u64, 32> = Volume::filled(0u64);
 
         // Is this spot here 0?
         assert_eq!(volume[(3, 5, 9)], 0u64);
@@ -148,7 +231,7 @@ mod tests {
     #[test]
     fn iter() {
         // Fill a volume up with zeroes
-        let mut volume: Volume<u64, 8> = Volume::filled(0u64);
+        let mut volume: CubicVolume<u64, 8> = Volume::filled(0u64);
 
         // Values correspond with sum of index
         volume[(0, 4, 4)] = 8;
@@ -169,10 +252,19 @@ mod tests {
         }
     }
 
+    #[test]
+    fn iter_covers_everything() {
+        let volume: CubicVolume<u64, 4> = Volume::filled(0u64);
+
+        assert_eq!(volume.iter().count(), 4 * 4 * 4);
+        assert_eq!(volume.iter_indices().count(), 4 * 4 * 4);
+        assert_eq!(volume.iter().last().map(|(idx, _)| idx), Some((3, 3, 3)));
+    }
+
     #[test]
     fn mut_in_iter() {
         // Fill a volume up with zeroes
-        let mut volume: Volume<u64, 8> = Volume::filled(0u64);
+        let mut volume: CubicVolume<u64, 8> = Volume::filled(0u64);
 
         // Values correspond with sum of index
         volume[(0, 4, 4)] = 8;
@@ -198,4 +290,44 @@ mod tests {
         assert_eq!(volume[(4, 4, 4)], 12*2);
         assert_eq!(volume[(7, 3, 6)], 12*2);
     }
+
+    #[test]
+    fn serialize() {
+        let mut volume: Volume<u64, 3, 2, 4> = Volume::filled(0u64);
+        for (n, idx) in volume.iter_indices().enumerate() {
+            volume[idx] = n as u64 * 3;
+        }
+
+        let bytes = bincode::serialize(&volume).unwrap();
+        let deserialized: Volume<u64, 3, 2, 4> = bincode::deserialize(&bytes).unwrap();
+        assert!(deserialized == volume);
+
+        // The wrong size doesn't deserialize
+        assert!(bincode::deserialize::<Volume<u64, 3, 2, 3>>(&bytes).is_err());
+        assert!(bincode::deserialize::<Volume<u64, 3, 2, 5>>(&bytes).is_err());
+    }
+
+    #[test]
+    fn non_cubic() {
+        let mut volume: Volume<u64, 2, 5, 3> = Volume::filled(0u64);
+        assert_eq!(volume.dims(), (2, 5, 3));
+
+        // Every dimension is bounds checked on its own
+        assert!(volume.get((1, 4, 2)).is_some());
+        assert!(volume.get((2, 0, 0)).is_none());
+        assert!(volume.get((0, 5, 0)).is_none());
+        assert!(volume.get((0, 0, 3)).is_none());
+        assert!(volume.get_mut((1, 3, 3)).is_none());
+
+        volume[(1, 4, 2)] = 7;
+        assert_eq!(volume.get((1, 4, 2)), Some(&7));
+
+        // X changes fastest, then Y, then Z
+        let indices: Vec<_> = volume.iter_indices().collect();
+        assert_eq!(indices.len(), 2 * 5 * 3);
+        assert_eq!(&indices[..3], &[(0, 0, 0), (1, 0, 0), (0, 1, 0)]);
+        assert_eq!(indices.last(), Some(&(1, 4, 2)));
+
+        assert_eq!(volume.iter().filter(|(_, &v)| v == 7).count(), 1);
+    }
 }
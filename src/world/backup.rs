@@ -0,0 +1,113 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many backups of a world are kept around by default. Older ones are deleted when a new one is made.
+pub(crate) const BACKUPS_TO_KEEP: usize = 5;
+
+/// Copy the world in `world_dir` into a new timestamped directory in `backup_dir`, then delete the oldest backups of
+/// that world until only `keep` are left. Returns the path of the new backup.
+///
+/// Backups aren't archives, just directories with the same files as the world, so a backup can be restored by copying
+/// it back in place of the world directory.
+///
+/// This should only be done while the world isn't being saved to, otherwise the backup might have some regions from
+/// before the save and some from after.
+pub(crate) fn backup_world<P: AsRef<Path>, Q: AsRef<Path>>(world_dir: P, backup_dir: Q, keep: usize) -> io::Result<PathBuf> {
+    let world_dir = world_dir.as_ref();
+    let backup_dir = backup_dir.as_ref();
+
+    let name = world_dir.file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "world directory has no name"))?;
+
+    // Milliseconds since the epoch, padded so that sorting the names sorts the backups by age
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    let prefix = format!("{}-", name);
+    let target = backup_dir.join(format!("{}{:016}", prefix, millis));
+
+    copy_dir(world_dir, &target)?;
+
+    let mut backups: Vec<PathBuf> = fs::read_dir(backup_dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| matches!(entry.file_name().to_str(), Some(file) if is_backup(file, &prefix)))
+        .map(|entry| entry.path())
+        .collect();
+    backups.sort();
+
+    let excess = backups.len().saturating_sub(keep.max(1));
+    for old in &backups[..excess] {
+        fs::remove_dir_all(old)?;
+    }
+
+    Ok(target)
+}
+
+/// Whether `file` is named like a backup made by `backup_world`, for a world with the given `prefix`
+fn is_backup(file: &str, prefix: &str) -> bool {
+    matches!(file.strip_prefix(prefix), Some(stamp) if stamp.len() == 16 && stamp.bytes().all(|b| b.is_ascii_digit()))
+}
+
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        // Leftovers from a save that didn't finish
+        if matches!(path.extension(), Some(ext) if ext == "tmp") {
+            continue;
+        }
+
+        if entry.file_type()?.is_dir() {
+            copy_dir(&path, &to.join(entry.file_name()))?;
+        } else {
+            fs::copy(&path, to.join(entry.file_name()))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::testing::test_dir;
+
+    #[test]
+    fn rotating_backups() {
+        let root = test_dir("backups");
+        let world = root.join("world");
+        let backups = root.join("backups");
+
+        fs::create_dir_all(world.join("sub")).unwrap();
+        fs::write(world.join("world.toml"), "format_version = 1").unwrap();
+        fs::write(world.join("sub").join("r.0.0.0.svr"), [1, 2, 3]).unwrap();
+        fs::write(world.join("r.0.0.0.svr.tmp"), [4]).unwrap();
+
+        // Something else in the backup directory that shouldn't be touched
+        fs::create_dir_all(backups.join("other-world-0000000000000001")).unwrap();
+
+        let mut made = Vec::new();
+        for _ in 0..4 {
+            made.push(backup_world(&world, &backups, 2).unwrap());
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+
+        let latest = made.last().unwrap();
+        assert_eq!(fs::read(latest.join("sub").join("r.0.0.0.svr")).unwrap(), [1, 2, 3]);
+        assert!(latest.join("world.toml").exists());
+        assert!(!latest.join("r.0.0.0.svr.tmp").exists());
+
+        // Only the last 2 are kept
+        assert!(!made[0].exists());
+        assert!(!made[1].exists());
+        assert!(made[2].exists());
+        assert!(backups.join("other-world-0000000000000001").exists());
+        assert_eq!(fs::read_dir(&backups).unwrap().count(), 3);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
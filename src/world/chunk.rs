@@ -1,17 +1,60 @@
 #![allow(unused)]
+use std::sync::atomic::{AtomicU32, Ordering};
+
 use bevy::prelude::*;
 use bevy::render::mesh::Indices;
 use bevy::render::render_resource::PrimitiveTopology;
-use crate::util::{Volume, VolumeIdx, FaceVectors, FaceMesh};
+use serde::{Deserialize, Deserializer, Serialize};
+use crate::util::{CubicVolume, Volume, VolumeIdx, FaceVectors, FaceMesh};
 use super::voxel::Voxel;
+use super::coords::{ChunkPos, Direction, LocalPos};
+
+#[cfg(all(feature = "chunk-16", feature = "chunk-64"))]
+compile_error!("only one of the chunk-16 and chunk-64 features can be enabled");
 
+/// How many voxels large chunks are on every side, set with the `chunk-16` and `chunk-64` features
+#[cfg(feature = "chunk-16")]
+pub(crate) const CHUNK_SIZE: usize = 16;
+#[cfg(feature = "chunk-64")]
+pub(crate) const CHUNK_SIZE: usize = 64;
+#[cfg(not(any(feature = "chunk-16", feature = "chunk-64")))]
 pub(crate) const CHUNK_SIZE: usize = 32;
-pub(crate) type ChunkPosition = IVec3;
 
+/// Bits of the `f32` returned by `voxel_scale`, starts out as 1.0
+static VOXEL_SCALE: AtomicU32 = AtomicU32::new(0x3f80_0000);
+
+/// How many world units large a voxel is on every side. Every world has its own, see `WorldMeta::voxel_scale`.
+pub(crate) fn voxel_scale() -> f32 {
+    f32::from_bits(VOXEL_SCALE.load(Ordering::Relaxed))
+}
+
+/// Set the voxel scale of the world that's being played. Has to happen before anything is placed in the world, since
+/// meshes, transforms and colliders are all sized by it.
+pub(crate) fn set_voxel_scale(scale: f32) {
+    VOXEL_SCALE.store(scale.to_bits(), Ordering::Relaxed);
+}
+
+#[derive(Clone, Serialize)]
 pub(crate) struct Chunk {
-    position: ChunkPosition,
+    position: ChunkPos,
+    // Worked out from the volume whenever a chunk is made, see `Chunk::new`
+    #[serde(skip)]
     empty: bool,
-    volume: Volume<Voxel, CHUNK_SIZE>,
+    volume: CubicVolume<Voxel, CHUNK_SIZE>,
+}
+
+impl<'de> Deserialize<'de> for Chunk {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(rename = "Chunk")]
+        struct Fields {
+            position: ChunkPos,
+            volume: CubicVolume<Voxel, CHUNK_SIZE>
+        }
+
+        let fields = Fields::deserialize(deserializer)?;
+        Ok(Self::new(fields.position, fields.volume))
+    }
 }
 
 pub(crate) struct ChunkMesh {
@@ -30,6 +73,16 @@ impl ChunkMesh {
             indices: Vec::new(),
         }
     }
+
+    /// Approximately how many bytes this mesh takes up
+    pub(crate) fn memory_size(&self) -> usize {
+        use std::mem::size_of_val;
+
+        size_of_val(self.vertices.as_slice())
+            + size_of_val(self.normals.as_slice())
+            + size_of_val(self.uvs.as_slice())
+            + size_of_val(self.indices.as_slice())
+    }
 }
 
 #[allow(clippy::from_over_into)]
@@ -47,7 +100,7 @@ impl Into<Mesh> for ChunkMesh {
 }
 
 impl Chunk {
-    pub(crate) fn random_chunk(position: ChunkPosition) -> Self {
+    pub(crate) fn random_chunk(position: ChunkPos) -> Self {
         //let mut volume = Volume::filled(Voxel::inactive());
         // let mut empty = true;
         //
@@ -65,7 +118,7 @@ impl Chunk {
         }
     }
 
-    pub(crate) fn new(position: ChunkPosition, data: Volume<Voxel, CHUNK_SIZE>) -> Self {
+    pub(crate) fn new(position: ChunkPos, data: CubicVolume<Voxel, CHUNK_SIZE>) -> Self {
         let mut empty = true;
         if data.iter().any(|(_, v)| v.active) {
             empty = false;
@@ -78,10 +131,30 @@ impl Chunk {
         }
     }
 
-    pub(crate) fn position(&self) -> ChunkPosition {
+    pub(crate) fn position(&self) -> ChunkPos {
         self.position
     }
 
+    pub(crate) fn is_empty(&self) -> bool {
+        self.empty
+    }
+
+    pub(crate) fn volume(&self) -> &CubicVolume<Voxel, CHUNK_SIZE> {
+        &self.volume
+    }
+
+    /// Replace the voxel at `pos`, returning the voxel that was there before.
+    pub(crate) fn set(&mut self, pos: LocalPos, voxel: Voxel) -> Voxel {
+        let old = std::mem::replace(&mut self.volume[pos.idx()], voxel);
+        if voxel.active {
+            self.empty = false;
+        } else if old.active {
+            self.empty = !self.volume.iter().any(|(_, v)| v.active);
+        }
+
+        old
+    }
+
     pub(crate) fn create_mesh(&self) -> ChunkMesh {
         let mut mesh = ChunkMesh::empty();
         let mut current_index = 0u32;
@@ -90,10 +163,10 @@ impl Chunk {
             if !voxel.active { continue; }
             let this_pos = volume_idx_to_vec(idx);
 
-            for (direction, neighbor_idx) in NeighborIterator::from_idx(idx) {
-                let neighbor_voxel = self.volume[neighbor_idx];
+            let local = LocalPos::from_idx(idx).unwrap();
+            for (direction, neighbor) in local.neighbors() {
+                let neighbor_voxel = self.volume[neighbor.idx()];
                 if !neighbor_voxel.active {
-                    let neighbor_pos = volume_idx_to_vec(neighbor_idx);
 
                     // todo: we're currently adding vertex positions in reverse and negating normals;
                     //  fix the underlying model instead of doing all this extra work
@@ -101,7 +174,7 @@ impl Chunk {
 
                     for vertex in direction.get_face_mesh() {
                         // Vertex position, added in reverse cause model is weird
-                        buf.push((Vec3::from(vertex.0) + this_pos).into());
+                        buf.push(((Vec3::from(vertex.0) + this_pos) * voxel_scale()).into());
 
                         // Vertex normal, negated for same reason as above
                         mesh.normals.push((-Vec3::from(vertex.1)).into());
@@ -129,22 +202,12 @@ impl Chunk {
     }
 }
 
-impl From<Chunk> for Volume<Voxel, CHUNK_SIZE> {
+impl From<Chunk> for CubicVolume<Voxel, CHUNK_SIZE> {
     fn from(chunk: Chunk) -> Self {
         chunk.volume
     }
 }
 
-#[derive(Copy, Clone)]
-enum Direction {
-    UP,
-    DOWN,
-    NORTH,
-    EAST,
-    SOUTH,
-    WEST
-}
-
 impl Direction {
     fn get_face_mesh(&self) -> FaceMesh {
         use crate::util::{
@@ -167,46 +230,6 @@ impl Direction {
     }
 }
 
-struct NeighborIterator {
-    neighbor_indices: [Option<(Direction, VolumeIdx)>; 6],
-    current_idx: usize
-}
-
-impl NeighborIterator {
-    fn from_idx(idx: VolumeIdx) -> Self {
-        let mut idxs = [None; 6];
-
-        if idx.0 + 1 < CHUNK_SIZE { idxs[0] = Some((Direction::EAST, (idx.0 + 1, idx.1, idx.2))) }
-        if idx.0.checked_sub(1).is_some() { idxs[1] = Some((Direction::WEST, (idx.0 - 1, idx.1, idx.2))) }
-
-        if idx.1 + 1 < CHUNK_SIZE { idxs[2] = Some((Direction::UP, (idx.0, idx.1 + 1, idx.2))) }
-        if idx.1.checked_sub(1).is_some() { idxs[3] = Some((Direction::DOWN, (idx.0, idx.1 - 1, idx.2))) }
-
-        if idx.2 + 1 < CHUNK_SIZE { idxs[4] = Some((Direction::SOUTH, (idx.0, idx.1, idx.2 + 1))) }
-        if idx.2.checked_sub(1).is_some() { idxs[5] = Some((Direction::NORTH, (idx.0, idx.1, idx.2 - 1))) }
-
-        Self {
-            neighbor_indices: idxs,
-            current_idx: 0
-        }
-    }
-}
-
-impl Iterator for NeighborIterator {
-    type Item = (Direction, VolumeIdx);
-    fn next(&mut self) -> Option<Self::Item> {
-        while self.current_idx < 6 {
-            let last_idx = self.current_idx;
-            self.current_idx += 1;
-            if let Some(idx) = self.neighbor_indices[last_idx] {
-                return Some(idx);
-            }
-        }
-
-        None
-    }
-}
-
 fn volume_idx_to_vec(idx: VolumeIdx) -> Vec3 {
     Vec3::new(idx.0 as f32, idx.1 as f32, idx.2 as f32)
 }
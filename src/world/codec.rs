@@ -0,0 +1,445 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::util::{Axis, CubicVolume, Volume};
+use crate::world::chunk::{Chunk, CHUNK_SIZE};
+use crate::world::coords::ChunkPos;
+use crate::world::voxel::Voxel;
+
+// Binary chunk format, all numbers are little endian:
+//   magic        4 bytes, "svck"
+//   version      u16
+//   position     3 * i32
+//   chunk size   u16, has to match CHUNK_SIZE
+//   palette      varint length, followed by one encoded voxel per entry
+//   runs         varint count, followed by (varint palette index, varint length) per run
+// Runs go through the volume in the same order as `Volume::iter`, and have to add up to exactly the size of the volume.
+
+const MAGIC: [u8; 4] = *b"svck";
+
+/// Version of the binary chunk format that `Chunk::encode` writes.
+pub(crate) const FORMAT_VERSION: u16 = 1;
+
+/// Turns the body of an encoded chunk (everything after the version) from one format version into the next one.
+pub(crate) type Migration = fn(&[u8]) -> Result<Vec<u8>, DecodeError>;
+
+/// `MIGRATIONS[n]` upgrades chunks from format version `n + 1` to `n + 2`. Whenever the format changes, bump
+/// `FORMAT_VERSION` and add a migration here so chunks saved by older versions of svep can still be loaded.
+const MIGRATIONS: [Migration; FORMAT_VERSION as usize - 1] = [];
+
+/// Upgrade `body` from `version` to the latest version, one version at a time.
+fn migrate<'a>(version: u16, body: &'a [u8], migrations: &[Migration]) -> Result<Cow<'a, [u8]>, DecodeError> {
+    let latest = migrations.len() + 1;
+    if version == 0 || version as usize > latest {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+
+    let mut body = Cow::Borrowed(body);
+    for migration in &migrations[version as usize - 1..] {
+        body = Cow::Owned(migration(&body)?);
+    }
+
+    Ok(body)
+}
+
+/// Reasons that a chunk couldn't be decoded
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum DecodeError {
+    /// The data doesn't start with the magic bytes, so it's probably not a chunk at all
+    BadMagic,
+    /// The data was written by a version of the format that we don't know how to read
+    UnsupportedVersion(u16),
+    /// The chunk has a different size than the chunks in this build
+    WrongChunkSize(usize),
+    /// The data ended in the middle of the chunk
+    UnexpectedEnd,
+    /// A variable length number was too long to be valid
+    BadVarint,
+    /// An encoded voxel didn't make sense
+    InvalidVoxel(u8),
+    /// A run referred to a palette entry that doesn't exist
+    BadPaletteIndex(usize),
+    /// The runs didn't add up to the size of the chunk
+    WrongVoxelCount { expected: usize, found: usize },
+    /// There was more data after the end of the chunk
+    TrailingBytes(usize),
+    /// The chunk was found somewhere it doesn't belong, this is its actual position
+    WrongPosition(ChunkPos)
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "data is not a chunk (bad magic bytes)"),
+            Self::UnsupportedVersion(version) => write!(f, "unsupported chunk format version {}", version),
+            Self::WrongChunkSize(size) => write!(f, "chunk size is {}, expected {}", size, CHUNK_SIZE),
+            Self::UnexpectedEnd => write!(f, "chunk data ended unexpectedly"),
+            Self::BadVarint => write!(f, "invalid variable length number"),
+            Self::InvalidVoxel(byte) => write!(f, "invalid voxel {:#04x}", byte),
+            Self::BadPaletteIndex(idx) => write!(f, "palette index {} is out of range", idx),
+            Self::WrongVoxelCount { expected, found } => write!(f, "chunk has {} voxels, expected {}", found, expected),
+            Self::TrailingBytes(n) => write!(f, "{} unexpected bytes after the end of the chunk", n),
+            Self::WrongPosition(pos) => write!(f, "chunk is actually at {}", pos)
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+/// Cursor over encoded data that returns errors instead of panicking when it runs out
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8]
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    pub(crate) fn remaining(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub(crate) fn bytes(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+        if self.bytes.len() < n {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+
+        let (out, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(out)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, DecodeError> {
+        let mut buf = [0; 2];
+        buf.copy_from_slice(self.bytes(2)?);
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    pub(crate) fn i32(&mut self) -> Result<i32, DecodeError> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.bytes(4)?);
+        Ok(i32::from_le_bytes(buf))
+    }
+
+    /// LEB128 encoded unsigned number
+    pub(crate) fn varint(&mut self) -> Result<usize, DecodeError> {
+        let mut out = 0usize;
+        for shift in (0..usize::BITS).step_by(7) {
+            let byte = self.u8()?;
+            let bits = (byte & 0x7f) as usize;
+
+            // Don't silently drop bits that don't fit
+            if (bits << shift) >> shift != bits {
+                return Err(DecodeError::BadVarint);
+            }
+
+            out |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(out);
+            }
+        }
+
+        Err(DecodeError::BadVarint)
+    }
+}
+
+/// Append `n` to `out` as a LEB128 encoded number
+pub(crate) fn write_varint(out: &mut Vec<u8>, mut n: usize) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+
+        if n == 0 {
+            out.push(byte);
+            return;
+        }
+
+        out.push(byte | 0x80);
+    }
+}
+
+// Voxels are a single byte: bit 0 is whether it's active, and bits 1-2 are its axis.
+fn encode_voxel(voxel: Voxel) -> u8 {
+    let axis = voxel.axis.index() as u8;
+    (voxel.active as u8) | (axis << 1)
+}
+
+fn decode_voxel(byte: u8) -> Result<Voxel, DecodeError> {
+    let axis = match (byte >> 1) & 0b11 {
+        0 => Axis::X,
+        1 => Axis::Y,
+        2 => Axis::Z,
+        _ => return Err(DecodeError::InvalidVoxel(byte))
+    };
+
+    if byte >> 3 != 0 {
+        return Err(DecodeError::InvalidVoxel(byte));
+    }
+
+    Ok(Voxel { active: byte & 1 != 0, axis })
+}
+
+/// Append the palette and runs making up `volume` to `out`.
+pub(crate) fn encode_volume(volume: &CubicVolume<Voxel, CHUNK_SIZE>, out: &mut Vec<u8>) {
+    let mut palette: Vec<Voxel> = Vec::new();
+    let mut palette_indices: HashMap<Voxel, usize> = HashMap::new();
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+
+    for (_, &voxel) in volume.iter() {
+        let idx = *palette_indices.entry(voxel).or_insert_with(|| {
+            palette.push(voxel);
+            palette.len() - 1
+        });
+
+        match runs.last_mut() {
+            Some((last, len)) if *last == idx => *len += 1,
+            _ => runs.push((idx, 1))
+        }
+    }
+
+    write_varint(out, palette.len());
+    out.extend(palette.iter().map(|&voxel| encode_voxel(voxel)));
+
+    write_varint(out, runs.len());
+    for (idx, len) in runs {
+        write_varint(out, idx);
+        write_varint(out, len);
+    }
+}
+
+/// Read a volume written by `encode_volume`.
+pub(crate) fn decode_volume(reader: &mut Reader) -> Result<CubicVolume<Voxel, CHUNK_SIZE>, DecodeError> {
+    let expected = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+
+    let palette_len = reader.varint()?;
+    // Every palette entry is a byte, so this can't be larger than what's left (and we don't want to allocate for it)
+    if palette_len > reader.remaining() {
+        return Err(DecodeError::UnexpectedEnd);
+    }
+    let palette = reader.bytes(palette_len)?
+        .iter()
+        .map(|&byte| decode_voxel(byte))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut volume = Volume::filled(Voxel::inactive());
+    let mut indices = volume.iter_indices();
+    let mut found = 0usize;
+
+    let run_count = reader.varint()?;
+    for _ in 0..run_count {
+        let idx = reader.varint()?;
+        let len = reader.varint()?;
+        let voxel = *palette.get(idx).ok_or(DecodeError::BadPaletteIndex(idx))?;
+
+        found = found.saturating_add(len);
+        if found > expected {
+            return Err(DecodeError::WrongVoxelCount { expected, found });
+        }
+
+        for vol_idx in indices.by_ref().take(len) {
+            volume[vol_idx] = voxel;
+        }
+    }
+
+    if found != expected {
+        return Err(DecodeError::WrongVoxelCount { expected, found });
+    }
+
+    Ok(volume)
+}
+
+impl Chunk {
+    /// Compact binary representation of this chunk, for saving to disk or sending over the network.
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&MAGIC);
+        out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+
+        let pos = self.position().0;
+        for component in [pos.x, pos.y, pos.z] {
+            out.extend_from_slice(&component.to_le_bytes());
+        }
+
+        out.extend_from_slice(&(CHUNK_SIZE as u16).to_le_bytes());
+        encode_volume(self.volume(), &mut out);
+
+        out
+    }
+
+    /// Read a chunk written by `encode`, possibly by an older version of svep.
+    pub(crate) fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(bytes);
+
+        if reader.bytes(MAGIC.len()).map_err(|_| DecodeError::BadMagic)? != MAGIC {
+            return Err(DecodeError::BadMagic);
+        }
+
+        let version = reader.u16()?;
+        let body = migrate(version, reader.bytes(reader.remaining())?, &MIGRATIONS)?;
+        let mut reader = Reader::new(&body);
+
+        let position = ChunkPos::new(reader.i32()?, reader.i32()?, reader.i32()?);
+
+        let size = reader.u16()? as usize;
+        if size != CHUNK_SIZE {
+            return Err(DecodeError::WrongChunkSize(size));
+        }
+
+        let volume = decode_volume(&mut reader)?;
+        if reader.remaining() != 0 {
+            return Err(DecodeError::TrailingBytes(reader.remaining()));
+        }
+
+        Ok(Chunk::new(position, volume))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_chunk() -> Chunk {
+        let mut volume: CubicVolume<Voxel, CHUNK_SIZE> = Volume::filled(Voxel::inactive());
+        for idx in volume.iter_indices() {
+            if idx.1 < 10 {
+                volume[idx] = Voxel::active();
+            }
+            if idx.0 == idx.1 && idx.1 == idx.2 {
+                volume[idx] = Voxel::active().with_axis(Axis::Z);
+            }
+        }
+
+        Chunk::new(ChunkPos::new(-4, 2, i32::MIN), volume)
+    }
+
+    #[test]
+    fn round_trip() {
+        let chunk = test_chunk();
+        let bytes = chunk.encode();
+
+        let decoded = Chunk::decode(&bytes).unwrap();
+        assert_eq!(decoded.position(), chunk.position());
+        assert!(decoded.volume() == chunk.volume());
+
+        // Uniform chunks compress down to almost nothing
+        let solid = Chunk::new(ChunkPos::new(0, 0, 0), Volume::filled(Voxel::active()));
+        let bytes = solid.encode();
+        assert!(bytes.len() < 32);
+        assert!(Chunk::decode(&bytes).unwrap().volume() == solid.volume());
+    }
+
+    #[test]
+    fn varints() {
+        for n in [0, 1, 127, 128, 300, CHUNK_SIZE.pow(3), usize::MAX] {
+            let mut bytes = Vec::new();
+            write_varint(&mut bytes, n);
+            assert_eq!(Reader::new(&bytes).varint(), Ok(n));
+        }
+
+        // Too many continuation bytes
+        assert_eq!(Reader::new(&[0xff; 11]).varint(), Err(DecodeError::BadVarint));
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = test_chunk().encode();
+
+        // Every possible truncation fails cleanly
+        for len in 0..bytes.len() {
+            assert!(Chunk::decode(&bytes[..len]).is_err());
+        }
+
+        let mut long = bytes.clone();
+        long.push(0);
+        assert_eq!(Chunk::decode(&long).err(), Some(DecodeError::TrailingBytes(1)));
+    }
+
+    #[test]
+    fn corrupted() {
+        let bytes = test_chunk().encode();
+
+        let mut bad = bytes.clone();
+        bad[0] = b'x';
+        assert_eq!(Chunk::decode(&bad).err(), Some(DecodeError::BadMagic));
+
+        let mut bad = bytes.clone();
+        bad[4] = 0xff;
+        assert!(matches!(Chunk::decode(&bad), Err(DecodeError::UnsupportedVersion(_))));
+
+        let mut bad = bytes.clone();
+        bad[18] = 0x11;
+        assert!(matches!(Chunk::decode(&bad), Err(DecodeError::WrongChunkSize(_))));
+
+        // Flipping bits anywhere might decode to a different chunk, but must never panic
+        for i in 0..bytes.len() {
+            for bit in 0..8 {
+                let mut bad = bytes.clone();
+                bad[i] ^= 1 << bit;
+                let _ = Chunk::decode(&bad);
+            }
+        }
+    }
+
+    #[test]
+    fn migrations() {
+        // Pretend that version 1 had the position as 3 i64s, and version 2 had a useless byte at the end
+        fn v1_to_v2(body: &[u8]) -> Result<Vec<u8>, DecodeError> {
+            let mut reader = Reader::new(body);
+            let mut out = Vec::new();
+            for _ in 0..3 {
+                let bytes = reader.bytes(8)?;
+                out.extend_from_slice(&bytes[..4]);
+            }
+            out.extend_from_slice(reader.bytes(reader.remaining())?);
+            Ok(out)
+        }
+        fn v2_to_v3(body: &[u8]) -> Result<Vec<u8>, DecodeError> {
+            body.split_last().map(|(_, rest)| rest.to_vec()).ok_or(DecodeError::UnexpectedEnd)
+        }
+        let migrations: [Migration; 2] = [v1_to_v2, v2_to_v3];
+
+        let current = test_chunk().encode();
+        let body = &current[MAGIC.len() + 2..];
+
+        let mut v2 = body.to_vec();
+        v2.push(0xaa);
+
+        let mut v1 = Vec::new();
+        for component in [-4i64, 2, i32::MIN as i64] {
+            v1.extend_from_slice(&component.to_le_bytes());
+        }
+        v1.extend_from_slice(&body[12..]);
+        v1.push(0xaa);
+
+        assert_eq!(migrate(3, body, &migrations).unwrap(), body);
+        assert_eq!(migrate(2, &v2, &migrations).unwrap(), body);
+        assert_eq!(migrate(1, &v1, &migrations).unwrap(), body);
+
+        // Versions from the future (or 0) are refused
+        assert_eq!(migrate(4, body, &migrations), Err(DecodeError::UnsupportedVersion(4)));
+        assert_eq!(migrate(0, body, &migrations), Err(DecodeError::UnsupportedVersion(0)));
+    }
+
+    #[test]
+    fn serde() {
+        let chunk = test_chunk();
+        let bytes = bincode::serialize(&chunk).unwrap();
+        let deserialized: Chunk = bincode::deserialize(&bytes).unwrap();
+
+        assert_eq!(deserialized.position(), chunk.position());
+        assert!(deserialized.volume() == chunk.volume());
+        assert!(!deserialized.is_empty());
+
+        // Whether the chunk is empty isn't saved, it's worked out from the volume again
+        let empty = Chunk::new(ChunkPos::new(0, 0, 0), Volume::filled(Voxel::inactive()));
+        let deserialized: Chunk = bincode::deserialize(&bincode::serialize(&empty).unwrap()).unwrap();
+        assert!(deserialized.is_empty());
+    }
+}
@@ -0,0 +1,184 @@
+use bevy::prelude::*;
+
+use crate::components::Collider;
+use crate::world::chunk::voxel_scale;
+use crate::world::coords::WorldPos;
+use crate::world::manager::ChunkManager;
+
+/// Boxes that are closer than this to a voxel (in voxels) are touching it, so rounding errors don't let boxes slip into
+/// voxels they're resting against.
+const EPSILON: f32 = 1e-4;
+
+/// Where a box ended up after moving through the world
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct Sweep {
+    pub(crate) position: Vec3,
+    /// Which axes (X, Y and Z) the box ran into something on
+    pub(crate) hit: [bool; 3]
+}
+
+impl ChunkManager {
+    /// Move a box from `position` by `motion`, one axis at a time (Y first, then X and Z), stopping it against solid
+    /// voxels. Every voxel layer the box passes through is checked, so it can't skip over thin walls when it's moving
+    /// fast. Voxels the box is already inside of don't stop it, and voxels in chunks that aren't loaded are solid.
+    pub(crate) fn sweep(&self, collider: &Collider, position: Vec3, motion: Vec3) -> Sweep {
+        let mut position = position;
+        let mut hit = [false; 3];
+
+        for axis in [1, 0, 2] {
+            if motion[axis] == 0.0 {
+                continue;
+            }
+
+            let (min, max) = collider.bounds(position);
+            let allowed = self.sweep_axis(min / voxel_scale(), max / voxel_scale(), axis, motion[axis] / voxel_scale());
+
+            position[axis] += allowed * voxel_scale();
+            hit[axis] = allowed != motion[axis] / voxel_scale();
+        }
+
+        Sweep { position, hit }
+    }
+
+    /// How far a box from `min` to `max` (in voxels) can move along `axis` before it runs into a solid voxel, up to
+    /// `distance` voxels (which is negative to move backwards).
+    fn sweep_axis(&self, min: Vec3, max: Vec3, axis: usize, distance: f32) -> f32 {
+        let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+
+        // Voxels the box overlaps on the other two axes
+        let first = |value: f32| (value + EPSILON).floor() as i32;
+        let last = |value: f32| (value - EPSILON).ceil() as i32 - 1;
+        let (a_range, b_range) = (first(min[a])..=last(max[a]), first(min[b])..=last(max[b]));
+
+        let layer_solid = |layer: i32| {
+            a_range.clone().any(|i| b_range.clone().any(|j| {
+                let mut pos = IVec3::ZERO;
+                pos[axis] = layer;
+                pos[a] = i;
+                pos[b] = j;
+                !matches!(self.voxel(WorldPos(pos)), Some(voxel) if !voxel.active)
+            }))
+        };
+
+        if distance > 0.0 {
+            // The layers in front of the box, starting at its front face
+            let front = max[axis];
+            let mut layer = (front - EPSILON).ceil() as i32;
+            while (layer as f32) < front + distance {
+                if layer_solid(layer) {
+                    return (layer as f32 - front).max(0.0);
+                }
+                layer += 1;
+            }
+        } else {
+            let front = min[axis];
+            let mut layer = (front + EPSILON).floor() as i32 - 1;
+            while (layer + 1) as f32 > front + distance {
+                if layer_solid(layer) {
+                    return ((layer + 1) as f32 - front).min(0.0);
+                }
+                layer -= 1;
+            }
+        }
+
+        distance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{CubicVolume, Volume};
+    use crate::world::chunk::{Chunk, CHUNK_SIZE};
+    use crate::world::coords::ChunkPos;
+    use crate::world::voxel::Voxel;
+
+    /// A 1x1x1 voxel box, with its lowest corner at its position
+    fn cube() -> Collider {
+        Collider { min: Vec3::ZERO, max: Vec3::splat(voxel_scale()) }
+    }
+
+    /// Empty chunks around the origin with a floor at y = -1
+    fn manager() -> ChunkManager {
+        let mut below: CubicVolume<Voxel, CHUNK_SIZE> = Volume::filled(Voxel::inactive());
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                below[(x, CHUNK_SIZE - 1, z)] = Voxel::active();
+            }
+        }
+
+        let mut manager = ChunkManager::default();
+        for x in -1..=0 {
+            for z in -1..=0 {
+                manager.insert(Chunk::new(ChunkPos::new(x, -1, z), below.clone()));
+                manager.insert(Chunk::new(ChunkPos::new(x, 0, z), Volume::filled(Voxel::inactive())));
+            }
+        }
+        manager
+    }
+
+    /// Position in voxels, converted to world units
+    fn at(x: f32, y: f32, z: f32) -> Vec3 {
+        Vec3::new(x, y, z) * voxel_scale()
+    }
+
+    #[test]
+    fn landing() {
+        let manager = manager();
+
+        let sweep = manager.sweep(&cube(), at(0.5, 3.0, 0.5), at(0.0, -5.0, 0.0));
+        assert_eq!(sweep.position, at(0.5, 0.0, 0.5));
+        assert_eq!(sweep.hit, [false, true, false]);
+
+        // Resting on the floor, moving sideways isn't stopped by it
+        let sweep = manager.sweep(&cube(), at(0.5, 0.0, 0.5), at(3.0, 0.0, -2.0));
+        assert_eq!(sweep.position, at(3.5, 0.0, -1.5));
+        assert_eq!(sweep.hit, [false; 3]);
+    }
+
+    #[test]
+    fn walls() {
+        let mut manager = manager();
+        manager.set_voxel(WorldPos::new(3, 0, 0), Voxel::active());
+        manager.set_voxel(WorldPos::new(-3, 0, 0), Voxel::active());
+
+        // Sliding along the wall on Z while being stopped on X
+        let sweep = manager.sweep(&cube(), at(0.0, 0.0, 0.0), at(5.0, 0.0, 0.5));
+        assert_eq!(sweep.position, at(2.0, 0.0, 0.5));
+        assert_eq!(sweep.hit, [true, false, false]);
+
+        let sweep = manager.sweep(&cube(), at(0.0, 0.0, 0.0), at(-5.0, 0.0, 0.0));
+        assert_eq!(sweep.position, at(-2.0, 0.0, 0.0));
+
+        // Only just overlapping the wall's row on Z is enough to be stopped, touching it isn't
+        assert!(manager.sweep(&cube(), at(0.0, 0.0, 0.99), at(5.0, 0.0, 0.0)).hit[0]);
+        assert!(!manager.sweep(&cube(), at(0.0, 0.0, 1.0), at(5.0, 0.0, 0.0)).hit[0]);
+    }
+
+    #[test]
+    fn no_tunneling() {
+        let mut manager = manager();
+        for y in 0..4 {
+            manager.set_voxel(WorldPos::new(10, y, 0), Voxel::active());
+        }
+
+        // Way more than the wall is thick in a single step
+        let sweep = manager.sweep(&cube(), at(0.0, 0.0, 0.0), at(25.0, 0.0, 0.0));
+        assert_eq!(sweep.position, at(9.0, 0.0, 0.0));
+
+        // Falling from high up
+        let sweep = manager.sweep(&cube(), at(-5.0, (CHUNK_SIZE - 2) as f32, 0.0), at(0.0, -1000.0, 0.0));
+        assert_eq!(sweep.position, at(-5.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn unloaded_chunks() {
+        let manager = manager();
+
+        // Chunks aren't loaded past x = CHUNK_SIZE, so the box stops there
+        let size = CHUNK_SIZE as f32;
+        let sweep = manager.sweep(&cube(), at(size - 2.0, 0.0, 0.0), at(10.0, 0.0, 0.0));
+        assert_eq!(sweep.position, at(size - 1.0, 0.0, 0.0));
+        assert!(sweep.hit[0]);
+    }
+}
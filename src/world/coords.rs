@@ -0,0 +1,276 @@
+use std::fmt;
+use std::ops::{Add, Sub};
+
+use bevy::prelude::*;
+use bevy::math::const_ivec3;
+use serde::{Deserialize, Serialize};
+
+use crate::util::{RegionPoint, VolumeIdx};
+use crate::world::chunk::{voxel_scale, CHUNK_SIZE};
+
+const SIZE: i32 = CHUNK_SIZE as i32;
+
+/// Position of a voxel in the world, in voxels relative to the world origin
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct WorldPos(pub(crate) IVec3);
+
+/// Position of a chunk, in chunks relative to the world origin
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct ChunkPos(pub(crate) IVec3);
+
+/// Position of a voxel within its chunk. Always within the bounds of a chunk.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub(crate) struct LocalPos(VolumeIdx);
+
+/// Position of something (like the player) in the world, in world units. A voxel is `voxel_scale()` world units large.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub(crate) struct FloatPos(pub(crate) Vec3);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Direction {
+    UP,
+    DOWN,
+    NORTH,
+    EAST,
+    SOUTH,
+    WEST
+}
+
+impl Direction {
+    /// Every direction, in the order +X, -X, +Y, -Y, +Z, -Z
+    pub(crate) const ALL: [Self; 6] = [Self::EAST, Self::WEST, Self::UP, Self::DOWN, Self::SOUTH, Self::NORTH];
+
+    /// Offset to the neighbor (of a voxel or chunk) in this direction
+    pub(crate) fn offset(self) -> IVec3 {
+        match self {
+            Self::UP => IVec3::Y,
+            Self::DOWN => -IVec3::Y,
+            Self::NORTH => -IVec3::Z,
+            Self::EAST => IVec3::X,
+            Self::SOUTH => IVec3::Z,
+            Self::WEST => -IVec3::X
+        }
+    }
+}
+
+impl WorldPos {
+    #[cfg(test)]
+    pub(crate) const fn new(x: i32, y: i32, z: i32) -> Self {
+        Self(const_ivec3!([x, y, z]))
+    }
+
+    /// The chunk this voxel is in
+    pub(crate) fn chunk(self) -> ChunkPos {
+        ChunkPos(IVec3::new(self.0.x.div_euclid(SIZE), self.0.y.div_euclid(SIZE), self.0.z.div_euclid(SIZE)))
+    }
+
+    /// Where in its chunk this voxel is
+    pub(crate) fn local(self) -> LocalPos {
+        LocalPos((
+            self.0.x.rem_euclid(SIZE) as usize,
+            self.0.y.rem_euclid(SIZE) as usize,
+            self.0.z.rem_euclid(SIZE) as usize
+        ))
+    }
+
+    /// The chunk this voxel is in, and where in the chunk it is
+    pub(crate) fn split(self) -> (ChunkPos, LocalPos) {
+        (self.chunk(), self.local())
+    }
+
+    /// Position of this voxel's corner with the lowest coordinates
+    pub(crate) fn corner(self) -> FloatPos {
+        FloatPos(self.0.as_vec3() * voxel_scale())
+    }
+}
+
+impl ChunkPos {
+    pub(crate) const fn new(x: i32, y: i32, z: i32) -> Self {
+        Self(const_ivec3!([x, y, z]))
+    }
+
+    /// The voxel in this chunk with the lowest coordinates
+    pub(crate) fn origin(self) -> WorldPos {
+        WorldPos(self.0 * SIZE)
+    }
+
+    /// Corner of this chunk with the lowest coordinates, e.g. for the translation of the chunk's mesh
+    pub(crate) fn corner(self) -> FloatPos {
+        self.origin().corner()
+    }
+
+    /// Middle of this chunk
+    pub(crate) fn center(self) -> FloatPos {
+        FloatPos(self.corner().0 + Self::extent() / 2.0)
+    }
+
+    /// Size of a chunk in world units
+    pub(crate) fn extent() -> Vec3 {
+        Vec3::splat(CHUNK_SIZE as f32 * voxel_scale())
+    }
+
+    /// Whether this is within `range` chunks of `center` along every axis
+    pub(crate) fn within(self, center: ChunkPos, range: IVec3) -> bool {
+        (self - center).abs().cmple(range).all()
+    }
+}
+
+impl LocalPos {
+    /// Position within a chunk, or `None` if it's outside the chunk
+    pub(crate) fn from_idx(idx: VolumeIdx) -> Option<Self> {
+        if idx.0 < CHUNK_SIZE && idx.1 < CHUNK_SIZE && idx.2 < CHUNK_SIZE {
+            Some(Self(idx))
+        } else {
+            None
+        }
+    }
+
+    /// Index into the chunk's volume
+    pub(crate) fn idx(self) -> VolumeIdx {
+        self.0
+    }
+
+    /// Offset from the chunk's origin
+    pub(crate) fn offset(self) -> IVec3 {
+        IVec3::new(self.0.0 as i32, self.0.1 as i32, self.0.2 as i32)
+    }
+
+    /// Every neighbor that's in the same chunk
+    pub(crate) fn neighbors(self) -> impl Iterator<Item = (Direction, Self)> {
+        Direction::ALL.into_iter().filter_map(move |direction| {
+            let pos = self.offset() + direction.offset();
+            if pos.cmplt(IVec3::ZERO).any() {
+                return None
+            }
+
+            Some((direction, Self::from_idx((pos.x as usize, pos.y as usize, pos.z as usize))?))
+        })
+    }
+}
+
+impl FloatPos {
+    /// The voxel this position is in
+    pub(crate) fn voxel(self) -> WorldPos {
+        WorldPos((self.0 / voxel_scale()).floor().as_ivec3())
+    }
+
+    /// The chunk this position is in
+    pub(crate) fn chunk(self) -> ChunkPos {
+        self.voxel().chunk()
+    }
+}
+
+impl From<LocalPos> for VolumeIdx {
+    fn from(pos: LocalPos) -> Self {
+        pos.0
+    }
+}
+
+impl From<Vec3> for FloatPos {
+    fn from(pos: Vec3) -> Self {
+        Self(pos)
+    }
+}
+
+macro_rules! int_pos_ops {
+    ($name:ident) => {
+        impl Add<IVec3> for $name {
+            type Output = Self;
+
+            fn add(self, offset: IVec3) -> Self {
+                Self(self.0 + offset)
+            }
+        }
+
+        impl Sub<IVec3> for $name {
+            type Output = Self;
+
+            fn sub(self, offset: IVec3) -> Self {
+                Self(self.0 - offset)
+            }
+        }
+
+        /// Offset from one position to the other
+        impl Sub for $name {
+            type Output = IVec3;
+
+            fn sub(self, other: Self) -> IVec3 {
+                self.0 - other.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}", self.0)
+            }
+        }
+
+        impl RegionPoint for $name {
+            fn component_min(self, other: Self) -> Self {
+                Self(self.0.min(other.0))
+            }
+
+            fn component_max(self, other: Self) -> Self {
+                Self(self.0.max(other.0))
+            }
+        }
+    };
+}
+
+int_pos_ops!(WorldPos);
+int_pos_ops!(ChunkPos);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn around_the_origin() {
+        let last = CHUNK_SIZE - 1;
+        let local = |x, y, z| LocalPos::from_idx((x, y, z)).unwrap();
+
+        assert_eq!(WorldPos::new(0, 0, 0).split(), (ChunkPos::new(0, 0, 0), local(0, 0, 0)));
+        assert_eq!(WorldPos::new(-1, 0, 0).split(), (ChunkPos::new(-1, 0, 0), local(last, 0, 0)));
+        assert_eq!(WorldPos::new(0, -1, 1).split(), (ChunkPos::new(0, -1, 0), local(0, last, 1)));
+        assert_eq!(WorldPos::new(SIZE - 1, 0, 0).split(), (ChunkPos::new(0, 0, 0), local(last, 0, 0)));
+        assert_eq!(WorldPos::new(SIZE, 0, 0).split(), (ChunkPos::new(1, 0, 0), local(0, 0, 0)));
+        assert_eq!(WorldPos::new(-SIZE, 0, 0).split(), (ChunkPos::new(-1, 0, 0), local(0, 0, 0)));
+        assert_eq!(WorldPos::new(0, 0, -SIZE - 1).split(), (ChunkPos::new(0, 0, -2), local(0, 0, last)));
+        assert_eq!(LocalPos::from_idx((CHUNK_SIZE, 0, 0)), None);
+
+        // Local neighbors stop at the edge of the chunk
+        let neighbors: Vec<_> = local(0, 0, last).neighbors().collect();
+        assert_eq!(neighbors, vec![
+            (Direction::EAST, local(1, 0, last)),
+            (Direction::UP, local(0, 1, last)),
+            (Direction::NORTH, local(0, 0, last - 1))
+        ]);
+
+        // And back again
+        for x in -2 * SIZE..2 * SIZE {
+            let pos = WorldPos::new(x, -x, x / 3);
+            let (chunk, local) = pos.split();
+            assert_eq!(chunk.origin() + local.offset(), pos);
+        }
+
+        assert_eq!(ChunkPos::new(-1, 0, 2).origin(), WorldPos::new(-SIZE, 0, 2 * SIZE));
+        assert_eq!(ChunkPos::new(-1, 0, 2).corner(), FloatPos(Vec3::new(-(SIZE as f32), 0.0, 2.0 * SIZE as f32) * voxel_scale()));
+        assert!(ChunkPos::new(-2, 1, 2).within(ChunkPos::new(0, 0, 0), IVec3::new(2, 1, 2)));
+        assert!(!ChunkPos::new(-2, 1, 2).within(ChunkPos::new(0, 0, 0), IVec3::new(2, 0, 2)));
+    }
+
+    #[test]
+    fn float_positions() {
+        let voxels = |x, y, z| FloatPos(Vec3::new(x, y, z) * voxel_scale());
+
+        assert_eq!(voxels(0.5, 0.0, 0.99).voxel(), WorldPos::new(0, 0, 0));
+        assert_eq!(voxels(-0.5, -0.0, -1.0).voxel(), WorldPos::new(-1, 0, -1));
+        assert_eq!(voxels(-0.01, 0.0, 0.0).chunk(), ChunkPos::new(-1, 0, 0));
+        assert_eq!(voxels(SIZE as f32 - 0.01, 0.0, 0.0).chunk(), ChunkPos::new(0, 0, 0));
+        assert_eq!(voxels(SIZE as f32, 0.0, 0.0).chunk(), ChunkPos::new(1, 0, 0));
+        assert_eq!(ChunkPos::new(1, 0, 0).corner(), WorldPos::new(SIZE, 0, 0).corner());
+        assert_eq!(ChunkPos::new(1, 0, 0).center(), voxels(1.5 * SIZE as f32, SIZE as f32 / 2.0, SIZE as f32 / 2.0));
+    }
+}
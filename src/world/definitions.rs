@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Name of the definition for solid voxels, which every definition file needs to have
+pub(crate) const SOLID: &str = "solid";
+
+/// How a kind of voxel looks
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct VoxelDefinition {
+    /// Red, green and blue, from 0 to 1
+    pub(crate) color: [f32; 3],
+    pub(crate) roughness: f32,
+    pub(crate) metallic: f32,
+    pub(crate) reflectance: f32
+}
+
+impl Default for VoxelDefinition {
+    fn default() -> Self {
+        Self {
+            color: [0.6, 0.6, 0.6],
+            roughness: 0.6,
+            metallic: 0.0,
+            reflectance: 0.001
+        }
+    }
+}
+
+impl VoxelDefinition {
+    /// Material to render voxels of this kind with
+    pub(crate) fn material(&self) -> StandardMaterial {
+        let [r, g, b] = self.color;
+        StandardMaterial {
+            base_color: Color::rgb(r, g, b),
+            metallic: self.metallic,
+            perceptual_roughness: self.roughness,
+            reflectance: self.reflectance,
+            .. Default::default()
+        }
+    }
+}
+
+/// Every kind of voxel, by name, from `resources/voxels.toml`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct VoxelDefinitions(HashMap<String, VoxelDefinition>);
+
+impl Default for VoxelDefinitions {
+    fn default() -> Self {
+        Self(HashMap::from([(SOLID.to_owned(), VoxelDefinition::default())]))
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum DefinitionError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    /// A definition that svep needs isn't there
+    Missing(&'static str)
+}
+
+impl fmt::Display for DefinitionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "{}", error),
+            Self::Parse(error) => write!(f, "{}", error),
+            Self::Missing(name) => write!(f, "there's no definition for '{}' voxels", name)
+        }
+    }
+}
+
+impl Error for DefinitionError {}
+
+impl From<io::Error> for DefinitionError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<toml::de::Error> for DefinitionError {
+    fn from(error: toml::de::Error) -> Self {
+        Self::Parse(error)
+    }
+}
+
+impl VoxelDefinitions {
+    pub(crate) fn load<P: AsRef<Path>>(path: P) -> Result<Self, DefinitionError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub(crate) fn parse(text: &str) -> Result<Self, DefinitionError> {
+        let definitions: Self = toml::from_str(text)?;
+        if !definitions.0.contains_key(SOLID) {
+            return Err(DefinitionError::Missing(SOLID));
+        }
+
+        Ok(definitions)
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&VoxelDefinition> {
+        self.0.get(name)
+    }
+
+    /// Definition of solid voxels
+    pub(crate) fn solid(&self) -> &VoxelDefinition {
+        // Always there, see `parse`
+        &self.0[SOLID]
+    }
+
+    /// Names of the definitions that are different in `other`, or only in one of them
+    pub(crate) fn changed(&self, other: &Self) -> Vec<String> {
+        let mut changed: Vec<String> = self.0.iter()
+            .filter(|(name, definition)| other.get(name) != Some(definition))
+            .map(|(name, _)| name.clone())
+            .chain(other.0.keys().filter(|name| !self.0.contains_key(*name)).cloned())
+            .collect();
+        changed.sort();
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing() {
+        let definitions = VoxelDefinitions::parse("[solid]\ncolor = [1.0, 0.0, 0.0]\n[glass]\nroughness = 0.1").unwrap();
+        assert_eq!(definitions.solid().color, [1.0, 0.0, 0.0]);
+        assert_eq!(definitions.solid().roughness, VoxelDefinition::default().roughness);
+        assert_eq!(definitions.get("glass").unwrap().roughness, 0.1);
+
+        assert!(matches!(VoxelDefinitions::parse("[glass]"), Err(DefinitionError::Missing(SOLID))));
+        assert!(matches!(VoxelDefinitions::parse("[solid]\ncolor = 1"), Err(DefinitionError::Parse(_))));
+
+        // The definitions in the repository are valid
+        VoxelDefinitions::load(concat!(env!("CARGO_MANIFEST_DIR"), "/resources/voxels.toml")).unwrap();
+    }
+
+    #[test]
+    fn changes() {
+        let old = VoxelDefinitions::parse("[solid]\n[glass]\n[stone]").unwrap();
+        let new = VoxelDefinitions::parse("[solid]\ncolor = [1.0, 1.0, 1.0]\n[stone]\n[dirt]").unwrap();
+
+        assert_eq!(old.changed(&new), vec!["dirt", "glass", "solid"]);
+        assert!(old.changed(&old.clone()).is_empty());
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::{HashSet, VecDeque};
+
+use crate::util::ConnectedRegion;
+use crate::world::coords::{Direction, WorldPos};
+use crate::world::manager::ChunkManager;
+use crate::world::voxel::Voxel;
+
+/// Result of flood filling the world from a single voxel
+#[derive(Clone, Debug)]
+pub(crate) struct WorldFlood {
+    /// Every voxel that was reached
+    pub(crate) voxels: Vec<WorldPos>,
+    pub(crate) region: ConnectedRegion<WorldPos>,
+    /// Whether the fill ran out of budget before it could reach everything
+    pub(crate) exhausted: bool,
+    /// Whether the fill ran into chunks that aren't loaded. The region might continue in those chunks.
+    pub(crate) reached_unloaded: bool
+}
+
+/// Result of finding every connected region in a part of the world
+#[derive(Clone, Debug)]
+pub(crate) struct WorldRegions {
+    pub(crate) regions: Vec<ConnectedRegion<WorldPos>>,
+    /// Whether the search ran out of budget before it could go through the entire area
+    pub(crate) exhausted: bool,
+    /// Whether the area includes chunks that aren't loaded
+    pub(crate) reached_unloaded: bool
+}
+
+impl ChunkManager {
+    /// Flood fill the world from `start`, through voxels matching `predicate`, across chunk borders.
+    /// Visits at most `max_visits` voxels. Voxels in chunks that aren't loaded never match.
+    /// Returns `None` if `start` doesn't match.
+    pub(crate) fn flood<F: FnMut(&Voxel) -> bool>(
+        &self,
+        start: WorldPos,
+        mut predicate: F,
+        max_visits: usize
+    ) -> Option<WorldFlood> {
+        if !matches!(self.voxel(start), Some(voxel) if predicate(voxel)) {
+            return None
+        }
+
+        let mut out = WorldFlood {
+            voxels: Vec::new(),
+            region: ConnectedRegion::new(start),
+            exhausted: false,
+            reached_unloaded: false
+        };
+
+        let mut visited = HashSet::from([start]);
+        let mut queue = VecDeque::from([start]);
+
+        while let Some(pos) = queue.pop_front() {
+            if out.voxels.len() >= max_visits {
+                out.exhausted = true;
+                break;
+            }
+
+            out.voxels.push(pos);
+            out.region.add(pos);
+
+            for direction in Direction::ALL {
+                let neighbor = pos + direction.offset();
+                if visited.contains(&neighbor) {
+                    continue;
+                }
+
+                match self.voxel(neighbor) {
+                    Some(voxel) if predicate(voxel) => {
+                        visited.insert(neighbor);
+                        queue.push_back(neighbor);
+                    },
+                    Some(_) => (),
+                    None => out.reached_unloaded = true
+                }
+            }
+        }
+
+        Some(out)
+    }
+
+    /// Find every 6-connected region of voxels matching `predicate` in the box between `min` and `max` (inclusive).
+    /// Regions are cut off at the edges of the box. Visits at most `max_visits` voxels, where every voxel in the box
+    /// that's looked at counts (whether it's loaded or not) as well as every voxel that's added to a region.
+    pub(crate) fn label_regions<F: FnMut(&Voxel) -> bool>(
+        &self,
+        min: WorldPos,
+        max: WorldPos,
+        mut predicate: F,
+        max_visits: usize
+    ) -> WorldRegions {
+        let mut out = WorldRegions {
+            regions: Vec::new(),
+            exhausted: false,
+            reached_unloaded: false
+        };
+
+        let in_bounds = |pos: WorldPos| pos.0.cmpge(min.0).all() && pos.0.cmple(max.0).all();
+        let mut visited = HashSet::new();
+        let mut visits = 0usize;
+        let mut queue = VecDeque::new();
+
+        'search: for z in min.0.z..=max.0.z {
+            for y in min.0.y..=max.0.y {
+                for x in min.0.x..=max.0.x {
+                    if visits >= max_visits {
+                        out.exhausted = true;
+                        break 'search;
+                    }
+
+                    visits += 1;
+                    let start = WorldPos::new(x, y, z);
+                    if visited.contains(&start) {
+                        continue;
+                    }
+
+                    match self.voxel(start) {
+                        Some(voxel) if predicate(voxel) => (),
+                        Some(_) => continue,
+                        None => {
+                            out.reached_unloaded = true;
+                            continue;
+                        }
+                    }
+
+                    let mut region = ConnectedRegion::new(start);
+                    visited.insert(start);
+                    queue.push_back(start);
+
+                    while let Some(pos) = queue.pop_front() {
+                        if visits >= max_visits {
+                            out.exhausted = true;
+                            out.regions.push(region);
+                            break 'search;
+                        }
+
+                        visits += 1;
+                        region.add(pos);
+
+                        for direction in Direction::ALL {
+                            let neighbor = pos + direction.offset();
+                            if !in_bounds(neighbor) || visited.contains(&neighbor) {
+                                continue;
+                            }
+
+                            if matches!(self.voxel(neighbor), Some(voxel) if predicate(voxel)) {
+                                visited.insert(neighbor);
+                                queue.push_back(neighbor);
+                            }
+                        }
+                    }
+
+                    out.regions.push(region);
+                }
+            }
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{CubicVolume, Volume};
+    use crate::world::chunk::{Chunk, CHUNK_SIZE};
+    use crate::world::coords::ChunkPos;
+
+    #[test]
+    fn flood_across_chunks() {
+        let mut manager = ChunkManager::default();
+        let size = CHUNK_SIZE as i32;
+
+        // A pillar of air going through 2 chunks (one of them at negative coordinates), inside otherwise solid chunks
+        let mut volume: CubicVolume<Voxel, CHUNK_SIZE> = Volume::filled(Voxel::active());
+        for y in 0..CHUNK_SIZE {
+            volume[(3, y, 4)] = Voxel::inactive();
+        }
+        manager.insert(Chunk::new(ChunkPos::new(0, -1, 0), volume.clone()));
+        manager.insert(Chunk::new(ChunkPos::new(0, 0, 0), volume));
+
+        let air = |v: &Voxel| !v.active;
+        let flood = manager.flood(WorldPos::new(3, 5, 4), air, usize::MAX).unwrap();
+        assert_eq!(flood.region.size, CHUNK_SIZE * 2);
+        assert_eq!(flood.region.min, WorldPos::new(3, -size, 4));
+        assert_eq!(flood.region.max, WorldPos::new(3, size - 1, 4));
+        assert!(!flood.exhausted);
+
+        // The pillar is open to the chunks above and below it, which aren't loaded
+        assert!(flood.reached_unloaded);
+
+        // Running out of budget stops the fill early
+        let flood = manager.flood(WorldPos::new(3, 5, 4), air, 10).unwrap();
+        assert_eq!(flood.voxels.len(), 10);
+        assert!(flood.exhausted);
+
+        // Can't start in solid voxels or unloaded chunks
+        assert!(manager.flood(WorldPos::new(0, 0, 0), air, usize::MAX).is_none());
+        assert!(manager.flood(WorldPos::new(size, 0, 0), air, usize::MAX).is_none());
+    }
+
+    #[test]
+    fn regions_across_chunks() {
+        let mut manager = ChunkManager::default();
+
+        let mut left: CubicVolume<Voxel, CHUNK_SIZE> = Volume::filled(Voxel::inactive());
+        let mut right = left.clone();
+        let last = CHUNK_SIZE - 1;
+
+        // A bar crossing the border between the chunks...
+        left[(last, 2, 2)] = Voxel::active();
+        left[(last - 1, 2, 2)] = Voxel::active();
+        right[(0, 2, 2)] = Voxel::active();
+
+        // ...and a floating voxel on its own
+        right[(5, 5, 5)] = Voxel::active();
+
+        manager.insert(Chunk::new(ChunkPos::new(-1, 0, 0), left));
+        manager.insert(Chunk::new(ChunkPos::new(0, 0, 0), right));
+
+        let solid = |v: &Voxel| v.active;
+        let found = manager.label_regions(WorldPos::new(-8, 0, 0), WorldPos::new(8, 8, 8), solid, usize::MAX);
+        assert!(!found.exhausted);
+        assert!(!found.reached_unloaded);
+        assert_eq!(found.regions, vec![
+            ConnectedRegion { size: 3, min: WorldPos::new(-2, 2, 2), max: WorldPos::new(0, 2, 2) },
+            ConnectedRegion { size: 1, min: WorldPos::new(5, 5, 5), max: WorldPos::new(5, 5, 5) },
+        ]);
+
+        // The box cuts the bar in half
+        let found = manager.label_regions(WorldPos::new(0, 0, 0), WorldPos::new(8, 8, 8), solid, usize::MAX);
+        assert_eq!(found.regions[0], ConnectedRegion { size: 1, min: WorldPos::new(0, 2, 2), max: WorldPos::new(0, 2, 2) });
+
+        // Looking through the box counts against the budget, even where nothing matches or nothing is loaded
+        let found = manager.label_regions(WorldPos::new(0, 100, 0), WorldPos::new(99, 199, 99), solid, 1000);
+        assert!(found.exhausted);
+        assert!(found.reached_unloaded);
+        assert!(found.regions.is_empty());
+    }
+}
@@ -1,46 +1,521 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
 use bevy::prelude::*;
 
-use noise::{NoiseFn, Perlin, Worley, Fbm, SuperSimplex};
-use crate::util::Volume;
+use noise::{NoiseFn, Perlin, Worley, Fbm, SuperSimplex, Seedable};
+use crate::util::{CubicVolume, Volume};
 
-use crate::world::chunk::{Chunk, ChunkPosition, CHUNK_SIZE};
+use crate::world::chunk::{Chunk, CHUNK_SIZE};
+use crate::world::coords::{ChunkPos, WorldPos};
 use crate::world::voxel::Voxel;
+use crate::world::region::{chunk_to_region, WorldStorage};
+use crate::world::meta::GeneratorParams;
+use crate::world::visibility::FaceConnectivity;
+
+/// How many voxels one unit of noise is across. The generator was tuned for 32 voxel chunks with one unit per chunk,
+/// noise is sampled at world coordinates so the terrain stays the same with other chunk sizes.
+const VOXELS_PER_NOISE_UNIT: f64 = 32.0;
+
+/// Approximately how many bytes the data of a loaded chunk takes up, including its voxels on the heap
+pub(crate) const CHUNK_DATA_SIZE: usize =
+    std::mem::size_of::<Chunk>() + std::mem::size_of::<Voxel>() * CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+
+/// How well the chunk cache is doing
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct CacheStats {
+    /// Chunks that were requested and already loaded
+    pub(crate) hits: u64,
+    /// Chunks that were requested and had to be loaded from storage or generated
+    pub(crate) misses: u64,
+    /// Chunks that were unloaded to stay within the memory budget
+    pub(crate) evictions: u64
+}
+
+impl CacheStats {
+    /// Fraction of requests that were hits, or `None` if nothing was requested yet
+    pub(crate) fn hit_rate(&self) -> Option<f64> {
+        let total = self.hits + self.misses;
+        (total > 0).then(|| self.hits as f64 / total as f64)
+    }
+}
 
-const PERLIN_THRESHOLD: f64 = 0.33;
-const CHUNK_SIZE_F64: f64 = CHUNK_SIZE as f64;
+/// What `ChunkManager::evict` did
+#[derive(Default)]
+pub(crate) struct Eviction {
+    /// Chunks that were unloaded, so their meshes can be removed too
+    pub(crate) unloaded: Vec<ChunkPos>,
+    /// Copies of changed chunks that have to be saved before they can be unloaded. They stay loaded until
+    /// `finish_saving` is called with them, and are unloaded by a later `evict` if saving them worked.
+    pub(crate) to_save: Vec<Chunk>
+}
+
+// Bookkeeping for a loaded chunk
+#[derive(Copy, Clone, Default)]
+struct ChunkUsage {
+    last_used: u64,
+    mesh_size: usize
+}
 
 pub(crate) struct ChunkManager {
-    chunks: Vec<Chunk>,
-    visible_chunks: Vec<ChunkPosition>,
-    noisegen: Worley
+    chunks: HashMap<ChunkPos, Chunk>,
+    visible_chunks: HashSet<ChunkPos>,
+    // Chunks within this range (along each axis) of this chunk are never evicted
+    keep_loaded: Option<(ChunkPos, IVec3)>,
+    connectivity: HashMap<ChunkPos, FaceConnectivity>,
+    noisegen: Worley,
+    generator: GeneratorParams,
+    // Shared with background saves, see `take_dirty`
+    storage: Option<Arc<WorldStorage>>,
+    // Chunks that were changed since they were loaded or generated, and need to be saved
+    dirty: HashSet<ChunkPos>,
+    // Chunks that were taken by `take_dirty` or `evict` and are still being saved
+    saving: HashSet<ChunkPos>,
+    // Chunks that will be unloaded once they're saved
+    evicting: HashSet<ChunkPos>,
+    // Chunks that were changed since their mesh was made
+    remesh: HashSet<ChunkPos>,
+    usage: HashMap<ChunkPos, ChunkUsage>,
+    // Incremented every time a chunk is used, so we know which ones were used least recently
+    clock: u64,
+    mesh_memory: usize,
+    memory_budget: Option<usize>,
+    stats: CacheStats
 }
 
 impl Default for ChunkManager {
     fn default() -> Self {
         Self {
-            chunks: Vec::new(),
-            visible_chunks: Vec::new(),
+            chunks: HashMap::new(),
+            visible_chunks: HashSet::new(),
+            keep_loaded: None,
+            connectivity: HashMap::new(),
             noisegen: Worley::new(),
+            generator: GeneratorParams::default(),
+            storage: None,
+            dirty: HashSet::new(),
+            saving: HashSet::new(),
+            evicting: HashSet::new(),
+            remesh: HashSet::new(),
+            usage: HashMap::new(),
+            clock: 0,
+            mesh_memory: 0,
+            memory_budget: None,
+            stats: CacheStats::default()
         }
     }
 }
 
 impl ChunkManager {
-    pub(crate) fn generate_new(&mut self, pos: ChunkPosition) -> Chunk {
-        let mut vol: Volume<_, 32> = Volume::filled(Voxel::inactive());
+    /// Chunk manager that loads chunks from `storage` before generating them, and saves changed chunks to it.
+    pub(crate) fn with_storage(storage: WorldStorage) -> Self {
+        Self {
+            storage: Some(Arc::new(storage)),
+            ..Default::default()
+        }
+    }
+
+    /// Generate new chunks with `seed` and `params` from now on.
+    pub(crate) fn set_generator(&mut self, seed: u32, params: GeneratorParams) {
+        self.noisegen = Worley::new().set_seed(seed);
+        self.generator = params;
+    }
+
+    pub(crate) fn get(&self, pos: ChunkPos) -> Option<&Chunk> {
+        self.chunks.get(&pos)
+    }
+
+    /// Voxel at `pos` (in voxels, relative to the world origin), if the chunk it's in is loaded.
+    pub(crate) fn voxel(&self, pos: WorldPos) -> Option<&Voxel> {
+        let (chunk, local) = pos.split();
+        self.get(chunk).map(|chunk| &chunk.volume()[local.idx()])
+    }
+
+    /// Replace the voxel at `pos` (in voxels, relative to the world origin), marking its chunk as changed.
+    /// Returns the voxel that was there before, or `None` if the chunk isn't loaded.
+    pub(crate) fn set_voxel(&mut self, pos: WorldPos, voxel: Voxel) -> Option<Voxel> {
+        let (chunk_pos, local) = pos.split();
+        let chunk = self.chunks.get_mut(&chunk_pos)?;
+        let old = chunk.set(local, voxel);
+
+        if old != voxel {
+            if old.active != voxel.active {
+                self.connectivity.insert(chunk_pos, FaceConnectivity::of(chunk));
+            }
+            self.dirty.insert(chunk_pos);
+            self.remesh.insert(chunk_pos);
+        }
+
+        self.touch(chunk_pos);
+
+        Some(old)
+    }
+
+    /// Mark chunks as changed, so they're saved with the next save.
+    pub(crate) fn mark_dirty<I: IntoIterator<Item = ChunkPos>>(&mut self, positions: I) {
+        self.dirty.extend(positions.into_iter().filter(|pos| self.chunks.contains_key(pos)));
+    }
+
+    /// Copies of every chunk that changed since it was loaded, so they can be saved somewhere else (like a background
+    /// task) without holding on to the manager. The chunks are no longer considered changed afterwards, and aren't
+    /// evicted until `finish_saving` is called with them.
+    ///
+    /// Chunks that are still being saved from last time are left for the next save, so two saves of the same chunk
+    /// can't finish in the wrong order.
+    pub(crate) fn take_dirty(&mut self) -> Vec<Chunk> {
+        let positions: Vec<_> = self.dirty.iter().filter(|pos| !self.saving.contains(pos)).copied().collect();
+        let mut taken = Vec::with_capacity(positions.len());
+        for pos in positions {
+            self.dirty.remove(&pos);
+            if let Some(chunk) = self.chunks.get(&pos) {
+                taken.push(chunk.clone());
+                self.saving.insert(pos);
+            }
+        }
+
+        taken
+    }
+
+    /// Saving the chunks at `positions` (from `take_dirty` or `evict`) is done. The ones in `failed` weren't saved, so
+    /// they're changed again and stay loaded.
+    pub(crate) fn finish_saving(&mut self, positions: &[ChunkPos], failed: &[ChunkPos]) {
+        for pos in positions {
+            self.saving.remove(pos);
+        }
+        for pos in failed {
+            self.evicting.remove(pos);
+        }
+        self.mark_dirty(failed.iter().copied());
+    }
+
+    /// Take the chunks whose voxels changed since they were last meshed, so their meshes can be made again.
+    pub(crate) fn take_remesh(&mut self) -> Vec<ChunkPos> {
+        self.remesh.drain().collect()
+    }
+
+    /// The storage chunks are loaded from and saved to, if there is one.
+    pub(crate) fn storage(&self) -> Option<Arc<WorldStorage>> {
+        self.storage.clone()
+    }
+
+    /// Add a chunk to the world, replacing any chunk that was already loaded at its position.
+    pub(crate) fn insert(&mut self, chunk: Chunk) -> &Chunk {
+        let pos = chunk.position();
+        self.connectivity.insert(pos, FaceConnectivity::of(&chunk));
+        self.chunks.insert(pos, chunk);
+        self.touch(pos);
+        &self.chunks[&pos]
+    }
+
+    /// Mark the chunk at `pos` as recently used, so it's one of the last to be evicted.
+    pub(crate) fn touch(&mut self, pos: ChunkPos) {
+        if self.chunks.contains_key(&pos) {
+            // It's being used again, so it shouldn't be unloaded after all
+            self.evicting.remove(&pos);
+            self.clock += 1;
+            self.usage.entry(pos).or_default().last_used = self.clock;
+        }
+    }
+
+    /// Record how big the mesh of the chunk at `pos` is, replacing any mesh it had before.
+    pub(crate) fn record_mesh(&mut self, pos: ChunkPos, size: usize) {
+        if let Some(usage) = self.usage.get_mut(&pos) {
+            self.mesh_memory = self.mesh_memory - usage.mesh_size + size;
+            usage.mesh_size = size;
+        }
+    }
+
+    /// Chunks that can currently be seen, these are never evicted.
+    pub(crate) fn set_visible_chunks(&mut self, visible: HashSet<ChunkPos>) {
+        self.visible_chunks = visible;
+    }
+
+    pub(crate) fn visible_chunks(&self) -> &HashSet<ChunkPos> {
+        &self.visible_chunks
+    }
+
+    /// Never evict chunks within `range` chunks of `center` along each axis, e.g. the ones around the player.
+    pub(crate) fn keep_loaded(&mut self, center: ChunkPos, range: IVec3) {
+        self.keep_loaded = Some((center, range));
+    }
+
+    /// Which faces of the chunk at `pos` can see each other, if it's loaded
+    pub(crate) fn connectivity(&self, pos: ChunkPos) -> Option<FaceConnectivity> {
+        self.connectivity.get(&pos).copied()
+    }
+
+    /// Approximately how many bytes loaded chunks and their meshes take up
+    pub(crate) fn memory_usage(&self) -> usize {
+        self.chunks.len() * CHUNK_DATA_SIZE + self.mesh_memory
+    }
+
+    /// Evict chunks when they take up more than `budget` bytes, or never if it's `None`.
+    pub(crate) fn set_memory_budget(&mut self, budget: Option<usize>) {
+        self.memory_budget = budget;
+    }
+
+    pub(crate) fn loaded_chunks(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Unload the least recently used chunks that aren't visible until we're within the memory budget again.
+    ///
+    /// Changed chunks have to be saved first, which is left to the caller so it can happen in the background. Without
+    /// storage they can't be saved at all, so they're never evicted.
+    pub(crate) fn evict(&mut self) -> Eviction {
+        let mut eviction = Eviction::default();
+
+        // Chunks that were saved since the last time, unless they have to be kept loaded again by now
+        let saved: Vec<_> = self.evicting.iter().filter(|pos| !self.saving.contains(pos)).copied().collect();
+        for pos in saved {
+            if self.is_kept(pos) {
+                self.evicting.remove(&pos);
+            } else {
+                self.unload(pos);
+                eviction.unloaded.push(pos);
+            }
+        }
+
+        // Chunks that are already being evicted will free their memory soon
+        let pending = self.evicting.iter()
+            .map(|pos| CHUNK_DATA_SIZE + self.usage.get(pos).map_or(0, |usage| usage.mesh_size))
+            .sum::<usize>();
+        let usage = self.memory_usage() - pending;
+        if let Some(budget) = self.memory_budget.filter(|&budget| usage > budget) {
+            self.pick_evictions(usage - budget, &mut eviction);
+        }
+
+        if !eviction.unloaded.is_empty() {
+            self.forget_unused_regions();
+        }
+        eviction
+    }
+
+    /// Whether the chunk at `pos` is visible or in the box that's kept loaded, so it can't be evicted
+    fn is_kept(&self, pos: ChunkPos) -> bool {
+        self.visible_chunks.contains(&pos)
+            || matches!(self.keep_loaded, Some((center, range)) if pos.within(center, range))
+    }
+
+    /// Evict the least recently used chunks that can be evicted until `excess` bytes would be freed
+    fn pick_evictions(&mut self, mut excess: usize, eviction: &mut Eviction) {
+        let can_save = self.storage.is_some();
+        let mut candidates: Vec<_> = self.chunks.keys()
+            .filter(|&&pos| !self.is_kept(pos) && !self.saving.contains(&pos))
+            .filter(|pos| can_save || !self.dirty.contains(pos))
+            .map(|&pos| (self.usage.get(&pos).copied().unwrap_or_default(), pos))
+            .collect();
+        candidates.sort_unstable_by_key(|(usage, _)| usage.last_used);
+
+        for (usage, pos) in candidates {
+            if excess == 0 {
+                break;
+            }
+            excess = excess.saturating_sub(CHUNK_DATA_SIZE + usage.mesh_size);
+
+            if self.dirty.remove(&pos) {
+                eviction.to_save.push(self.chunks[&pos].clone());
+                self.saving.insert(pos);
+                self.evicting.insert(pos);
+            } else {
+                self.unload(pos);
+                eviction.unloaded.push(pos);
+            }
+        }
+    }
+
+    fn unload(&mut self, pos: ChunkPos) {
+        self.chunks.remove(&pos);
+        self.evicting.remove(&pos);
+        self.connectivity.remove(&pos);
+        self.visible_chunks.remove(&pos);
+        self.remesh.remove(&pos);
+        if let Some(usage) = self.usage.remove(&pos) {
+            self.mesh_memory -= usage.mesh_size;
+        }
+        self.stats.evictions += 1;
+    }
+
+    /// Let storage forget the regions none of the loaded chunks are in anymore
+    fn forget_unused_regions(&self) {
+        if let Some(storage) = &self.storage {
+            let used: HashSet<_> = self.chunks.keys().map(|&pos| chunk_to_region(pos).0).collect();
+            storage.retain_regions(|pos| used.contains(&pos));
+        }
+    }
+
+    pub(crate) fn generate_new(&mut self, pos: ChunkPos) -> &Chunk {
+        let mut vol: CubicVolume<_, CHUNK_SIZE> = Volume::filled(Voxel::inactive());
 
         for idx in vol.iter_indices() {
-            let x = (idx.0 as f64 / CHUNK_SIZE_F64) + (pos.x as f64);
-            let y = (idx.1 as f64 / CHUNK_SIZE_F64) + (pos.y as f64);
-            let z = (idx.2 as f64 / CHUNK_SIZE_F64) + (pos.z as f64);
+            let voxel = pos.origin() + IVec3::new(idx.0 as i32, idx.1 as i32, idx.2 as i32);
+            let at = voxel.0.as_dvec3() / VOXELS_PER_NOISE_UNIT / self.generator.scale;
 
-            let noise = self.noisegen.get([x/3.0, y/3.0, z/3.0]);
-            if noise > PERLIN_THRESHOLD {
+            let noise = self.noisegen.get(at.to_array());
+            if noise > self.generator.threshold {
                 vol[idx].active = true;
             }
         }
 
-        // self.chunks.push(Chunk::new(pos, vol));
-        Chunk::new(pos, vol)
+        self.insert(Chunk::new(pos, vol))
     }
-}
\ No newline at end of file
+
+    /// Get the chunk at `pos` if it's loaded, otherwise load it from storage, or generate it if it was never saved.
+    pub(crate) fn load_or_generate(&mut self, pos: ChunkPos) -> &Chunk {
+        if self.chunks.contains_key(&pos) {
+            self.stats.hits += 1;
+            self.touch(pos);
+            return &self.chunks[&pos]
+        }
+
+        self.stats.misses += 1;
+        if let Some(storage) = &self.storage {
+            match storage.load_chunk(pos) {
+                Ok(Some(chunk)) => return self.insert(chunk),
+                Ok(None) => (),
+                // Generating a new chunk means whatever was saved will be overwritten if the chunk is changed and saved
+                // again, but it's that or not having a chunk here at all.
+                Err(error) => error!("Couldn't load chunk at {}, generating it instead: {}", pos, error)
+            }
+        }
+
+        self.generate_new(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::testing::test_dir;
+    use crate::world::region::REGION_SIZE;
+
+    #[test]
+    fn lru_eviction() {
+        let dir = test_dir("eviction");
+        let mut manager = ChunkManager::with_storage(WorldStorage::open(&dir).unwrap());
+        let pos = |x| ChunkPos::new(x, 0, 0);
+
+        for x in 0..5 {
+            manager.load_or_generate(pos(x));
+        }
+        manager.record_mesh(pos(1), 1000);
+        assert_eq!(manager.memory_usage(), 5 * CHUNK_DATA_SIZE + 1000);
+        assert_eq!(manager.stats(), CacheStats { hits: 0, misses: 5, evictions: 0 });
+
+        // Nothing happens while we're within budget
+        manager.set_memory_budget(Some(5 * CHUNK_DATA_SIZE + 1000));
+        assert!(manager.evict().unloaded.is_empty());
+
+        // Chunks 0 and 2 are used again, 2 is changed and chunk 3 is kept loaded, so 1 and 4 are the least recently used
+        // chunks that can be evicted.
+        manager.load_or_generate(pos(0));
+        manager.load_or_generate(pos(2));
+        manager.mark_dirty([pos(2)]);
+        manager.keep_loaded(pos(3), IVec3::ZERO);
+        assert_eq!(manager.stats().hits, 2);
+
+        manager.set_memory_budget(Some(3 * CHUNK_DATA_SIZE));
+        let eviction = manager.evict();
+        assert_eq!(eviction.unloaded, vec![pos(1), pos(4)]);
+        assert!(eviction.to_save.is_empty());
+        assert_eq!(manager.memory_usage(), 3 * CHUNK_DATA_SIZE);
+        assert_eq!(manager.stats().evictions, 2);
+
+        // The changed chunk has to be saved before it's evicted, and stays loaded until then
+        manager.set_memory_budget(Some(0));
+        let eviction = manager.evict();
+        assert_eq!(eviction.unloaded, vec![pos(0)]);
+        assert_eq!(eviction.to_save.iter().map(|chunk| chunk.position()).collect::<Vec<_>>(), vec![pos(2)]);
+        assert!(manager.get(pos(2)).is_some());
+        assert!(manager.evict().unloaded.is_empty());
+
+        let storage = manager.storage().unwrap();
+        storage.save_chunks(&eviction.to_save).unwrap();
+        manager.finish_saving(&[pos(2)], &[]);
+        assert_eq!(manager.evict().unloaded, vec![pos(2)]);
+
+        // A saved chunk isn't unloaded after all if it has to be kept loaded again by then
+        let misses = manager.stats().misses;
+        manager.load_or_generate(pos(2));
+        assert_eq!(manager.stats().misses, misses + 1);
+        manager.mark_dirty([pos(2)]);
+        let eviction = manager.evict();
+        assert_eq!(eviction.to_save.len(), 1);
+        manager.keep_loaded(pos(2), IVec3::X);
+        manager.finish_saving(&[pos(2)], &[]);
+        assert!(manager.evict().unloaded.is_empty());
+        assert!(manager.get(pos(2)).is_some());
+        assert!(manager.get(pos(3)).is_some());
+        assert!(storage.load_chunk(pos(2)).unwrap().is_some());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_unsaved_changes() {
+        // Without storage changed chunks can't be saved, so they're never evicted
+        let mut manager = ChunkManager::default();
+        manager.load_or_generate(ChunkPos::new(0, 0, 0));
+        manager.load_or_generate(ChunkPos::new(1, 0, 0));
+        manager.mark_dirty([ChunkPos::new(0, 0, 0)]);
+
+        manager.set_memory_budget(Some(0));
+        let eviction = manager.evict();
+        assert_eq!(eviction.unloaded, vec![ChunkPos::new(1, 0, 0)]);
+        assert!(eviction.to_save.is_empty());
+        assert!(manager.get(ChunkPos::new(0, 0, 0)).is_some());
+    }
+
+    #[test]
+    fn evicting_while_saving() {
+        let dir = test_dir("evicting-while-saving");
+        let mut manager = ChunkManager::with_storage(WorldStorage::open(&dir).unwrap());
+        manager.load_or_generate(ChunkPos::new(0, 0, 0));
+        manager.load_or_generate(ChunkPos::new(1, 0, 0));
+        manager.mark_dirty([ChunkPos::new(0, 0, 0)]);
+
+        // The changed chunk is being saved in the background, so it isn't evicted yet
+        assert_eq!(manager.take_dirty().len(), 1);
+        manager.set_memory_budget(Some(0));
+        let eviction = manager.evict();
+        assert_eq!(eviction.unloaded, vec![ChunkPos::new(1, 0, 0)]);
+        assert!(eviction.to_save.is_empty());
+
+        // Changing it again doesn't let another save start before the first one is done
+        manager.mark_dirty([ChunkPos::new(0, 0, 0)]);
+        assert!(manager.take_dirty().is_empty());
+
+        // Saving it failed, so it has to be saved when it's evicted instead. That fails too, so it stays loaded.
+        manager.finish_saving(&[ChunkPos::new(0, 0, 0)], &[ChunkPos::new(0, 0, 0)]);
+        let eviction = manager.evict();
+        assert_eq!(eviction.to_save.len(), 1);
+        manager.finish_saving(&[ChunkPos::new(0, 0, 0)], &[ChunkPos::new(0, 0, 0)]);
+        assert!(manager.evict().unloaded.is_empty());
+        assert!(manager.get(ChunkPos::new(0, 0, 0)).is_some());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn forgets_unused_regions() {
+        let dir = test_dir("forgets-unused-regions");
+        let mut manager = ChunkManager::with_storage(WorldStorage::open(&dir).unwrap());
+        let chunks = [ChunkPos::new(0, 0, 0), ChunkPos::new(REGION_SIZE, 0, 0)];
+        for pos in chunks {
+            manager.load_or_generate(pos);
+        }
+        let storage = manager.storage().unwrap();
+        assert_eq!(storage.cached_regions(), 2);
+
+        manager.touch(chunks[0]);
+        manager.set_memory_budget(Some(CHUNK_DATA_SIZE));
+        assert_eq!(manager.evict().unloaded, vec![chunks[1]]);
+        assert_eq!(storage.cached_regions(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,296 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::components::Player;
+use crate::settings::WorldSettings;
+use crate::world::chunk::CHUNK_SIZE;
+
+/// Version of the world format (the layout of the world directory and `world.toml`) that this build writes.
+/// Chunks have their own version, see `codec::FORMAT_VERSION`.
+pub(crate) const WORLD_FORMAT_VERSION: u32 = 1;
+
+/// Version of svep itself, recorded in the world so we can tell players which version they need to open it
+pub(crate) const SVEP_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+const META_FILE: &str = "world.toml";
+
+/// Upgrades the contents of `world.toml` from one world format version to the next.
+type WorldMigration = fn(&mut toml::value::Table);
+
+/// `WORLD_MIGRATIONS[n]` upgrades a world from format version `n + 1` to `n + 2`.
+const WORLD_MIGRATIONS: [WorldMigration; WORLD_FORMAT_VERSION as usize - 1] = [];
+
+/// Everything about a world that isn't chunks, stored as `world.toml` in the world directory
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct WorldMeta {
+    pub(crate) format_version: u32,
+    /// Version of svep that last saved this world
+    pub(crate) svep_version: String,
+    pub(crate) seed: u32,
+    pub(crate) spawn: Vec3,
+    /// Angle of the sun, in radians
+    pub(crate) time_of_day: f32,
+    /// Size of the chunks in this world, it can only be opened by builds with the same chunk size.
+    /// Worlds from before this was recorded all have 32 voxel chunks.
+    #[serde(default = "default_chunk_size")]
+    pub(crate) chunk_size: usize,
+    /// How many world units large a voxel is, see `chunk::voxel_scale`. Worlds from before this was recorded have 1.0.
+    #[serde(default = "default_voxel_scale")]
+    pub(crate) voxel_scale: f32,
+    pub(crate) generator: GeneratorParams,
+    /// The player as they were when the world was last saved, if it ever was
+    pub(crate) player: Option<Player>
+}
+
+fn default_chunk_size() -> usize {
+    32
+}
+
+fn default_voxel_scale() -> f32 {
+    1.0
+}
+
+/// Settings for the terrain generator
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct GeneratorParams {
+    /// Name of the generator, the only one right now is "worley"
+    pub(crate) name: String,
+    /// Noise values above this are solid
+    pub(crate) threshold: f64,
+    /// One unit of noise spans `32 * scale` voxels, larger values make larger features
+    pub(crate) scale: f64
+}
+
+impl Default for GeneratorParams {
+    fn default() -> Self {
+        Self {
+            name: "worley".to_owned(),
+            threshold: 0.33,
+            scale: 3.0
+        }
+    }
+}
+
+/// Reasons that a world couldn't be opened or saved
+#[derive(Debug)]
+pub(crate) enum WorldError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Serialize(toml::ser::Error),
+    /// `world.toml` doesn't say which format version it's in, or the version doesn't make sense
+    MissingVersion,
+    /// The world was saved by a newer version of svep, in a format that this version doesn't understand
+    TooNew { format_version: u32, svep_version: String },
+    UnknownGenerator(String),
+    /// The world's chunks are a different size than this build's
+    WrongChunkSize(usize),
+    /// The world's voxel scale isn't a positive number
+    InvalidVoxelScale(f32)
+}
+
+impl fmt::Display for WorldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "{}", error),
+            Self::Parse(error) => write!(f, "invalid {}: {}", META_FILE, error),
+            Self::Serialize(error) => write!(f, "couldn't write {}: {}", META_FILE, error),
+            Self::MissingVersion => write!(f, "{} is missing a valid format_version", META_FILE),
+            Self::TooNew { format_version, svep_version } => write!(
+                f,
+                "world was saved by svep {} (world format {}), but this is svep {} which only supports up to world format {}",
+                svep_version, format_version, SVEP_VERSION, WORLD_FORMAT_VERSION
+            ),
+            Self::UnknownGenerator(name) => write!(f, "unknown world generator '{}'", name),
+            Self::WrongChunkSize(size) => write!(
+                f,
+                "world has {} voxel chunks, but this build of svep uses {} voxel chunks",
+                size, CHUNK_SIZE
+            ),
+            Self::InvalidVoxelScale(scale) => write!(f, "voxel_scale has to be a positive number, not {}", scale)
+        }
+    }
+}
+
+impl Error for WorldError {}
+
+impl From<io::Error> for WorldError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<toml::de::Error> for WorldError {
+    fn from(error: toml::de::Error) -> Self {
+        Self::Parse(error)
+    }
+}
+
+impl From<toml::ser::Error> for WorldError {
+    fn from(error: toml::ser::Error) -> Self {
+        Self::Serialize(error)
+    }
+}
+
+impl WorldMeta {
+    /// Metadata for a brand new world
+    pub(crate) fn new(seed: u32) -> Self {
+        Self {
+            format_version: WORLD_FORMAT_VERSION,
+            svep_version: SVEP_VERSION.to_owned(),
+            seed,
+            spawn: Vec3::new(0.0, 1.0, 0.0),
+            time_of_day: 0.0,
+            chunk_size: CHUNK_SIZE,
+            voxel_scale: 1.0,
+            generator: GeneratorParams::default(),
+            player: None
+        }
+    }
+
+    /// Read `world.toml` from the world in `dir`, upgrading it if it's from an older version of svep.
+    /// If there's no world in `dir` yet, a new one is created with the seed and voxel scale from `new_world`.
+    pub(crate) fn load_or_create<P: AsRef<Path>>(dir: P, new_world: &WorldSettings) -> Result<Self, WorldError> {
+        let path = dir.as_ref().join(META_FILE);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                let mut meta = Self::new(new_world.seed.unwrap_or_else(rand::random));
+                meta.voxel_scale = new_world.voxel_scale;
+                meta.save(dir)?;
+                return Ok(meta);
+            },
+            Err(error) => return Err(error.into())
+        };
+
+        let mut table: toml::value::Table = toml::from_str(&text)?;
+        let version = table.get("format_version")
+            .and_then(|version| version.as_integer())
+            .filter(|&version| version >= 1)
+            .ok_or(WorldError::MissingVersion)?;
+
+        if version > WORLD_FORMAT_VERSION as i64 {
+            let svep_version = table.get("svep_version")
+                .and_then(|version| version.as_str())
+                .unwrap_or("(unknown)")
+                .to_owned();
+            return Err(WorldError::TooNew { format_version: version as u32, svep_version });
+        }
+
+        // Upgrade one version at a time
+        let migrated = version < WORLD_FORMAT_VERSION as i64;
+        for migration in &WORLD_MIGRATIONS[version as usize - 1..] {
+            migration(&mut table);
+        }
+
+        let mut meta: Self = toml::Value::Table(table).try_into()?;
+        if meta.generator.name != "worley" {
+            return Err(WorldError::UnknownGenerator(meta.generator.name));
+        }
+        if meta.chunk_size != CHUNK_SIZE {
+            return Err(WorldError::WrongChunkSize(meta.chunk_size));
+        }
+        if !(meta.voxel_scale.is_finite() && meta.voxel_scale > 0.0) {
+            return Err(WorldError::InvalidVoxelScale(meta.voxel_scale));
+        }
+
+        if migrated {
+            meta.format_version = WORLD_FORMAT_VERSION;
+            meta.save(dir)?;
+        }
+
+        Ok(meta)
+    }
+
+    /// Write this to `world.toml` in `dir`. Like region files, this goes through a temporary file so a crash can't
+    /// leave a half written file behind.
+    pub(crate) fn save<P: AsRef<Path>>(&self, dir: P) -> Result<(), WorldError> {
+        let mut meta = self.clone();
+        meta.svep_version = SVEP_VERSION.to_owned();
+
+        let path = dir.as_ref().join(META_FILE);
+        let tmp_path = path.with_extension("toml.tmp");
+
+        fs::create_dir_all(dir.as_ref())?;
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(toml::to_string(&meta)?.as_bytes())?;
+        file.sync_all()?;
+        drop(file);
+
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::testing::test_dir;
+
+    #[test]
+    fn round_trip() {
+        let dir = test_dir("meta-round-trip");
+
+        // A new world is created when there isn't one
+        let settings = WorldSettings { seed: Some(7), voxel_scale: 0.5, ..Default::default() };
+        let mut meta = WorldMeta::load_or_create(&dir, &settings).unwrap();
+        assert_eq!(meta.seed, 7);
+        assert_eq!(meta.voxel_scale, 0.5);
+        assert_eq!(meta.format_version, WORLD_FORMAT_VERSION);
+        assert!(dir.join(META_FILE).exists());
+
+        meta.time_of_day = 1.5;
+        meta.player = Some(Player { position: Vec3::new(1.0, -2.0, 3.0), grounded: true, slot: 2, look: (-0.25, 0.5), ..Default::default() });
+        meta.save(&dir).unwrap();
+
+        assert_eq!(WorldMeta::load_or_create(&dir, &Default::default()).unwrap(), meta);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn too_new() {
+        let dir = test_dir("meta-too-new");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(META_FILE), "format_version = 9999\nsvep_version = \"9.0.0\"\nsome_new_thing = true\n").unwrap();
+
+        let error = WorldMeta::load_or_create(&dir, &Default::default()).unwrap_err();
+        assert!(matches!(&error, WorldError::TooNew { format_version: 9999, svep_version } if svep_version == "9.0.0"));
+        assert!(error.to_string().contains("svep 9.0.0"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invalid() {
+        let dir = test_dir("meta-invalid");
+        fs::create_dir_all(&dir).unwrap();
+
+        fs::write(dir.join(META_FILE), "seed = 4").unwrap();
+        assert!(matches!(WorldMeta::load_or_create(&dir, &Default::default()), Err(WorldError::MissingVersion)));
+
+        fs::write(dir.join(META_FILE), "this isn't toml").unwrap();
+        assert!(matches!(WorldMeta::load_or_create(&dir, &Default::default()), Err(WorldError::Parse(_))));
+
+        let mut meta = WorldMeta::new(1);
+        meta.generator.name = "flat".to_owned();
+        meta.save(&dir).unwrap();
+        assert!(matches!(WorldMeta::load_or_create(&dir, &Default::default()), Err(WorldError::UnknownGenerator(_))));
+
+        let mut meta = WorldMeta::new(1);
+        meta.chunk_size = CHUNK_SIZE * 2;
+        meta.save(&dir).unwrap();
+        assert!(matches!(WorldMeta::load_or_create(&dir, &Default::default()), Err(WorldError::WrongChunkSize(size)) if size == CHUNK_SIZE * 2));
+
+        let mut meta = WorldMeta::new(1);
+        meta.voxel_scale = -0.5;
+        meta.save(&dir).unwrap();
+        assert!(matches!(WorldMeta::load_or_create(&dir, &Default::default()), Err(WorldError::InvalidVoxelScale(_))));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
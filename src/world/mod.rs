@@ -1,4 +1,17 @@
 pub(crate) mod chunk;
+pub(crate) mod coords;
 pub(crate) mod manager;
+// Nothing floods the world outside of tests yet
+#[cfg(test)]
+pub(crate) mod flood;
+pub(crate) mod codec;
+pub(crate) mod region;
+pub(crate) mod meta;
+pub(crate) mod backup;
+pub(crate) mod queue;
+pub(crate) mod visibility;
+pub(crate) mod raycast;
+pub(crate) mod collision;
+pub(crate) mod definitions;
 
-mod voxel;
+pub(crate) mod voxel;
@@ -0,0 +1,221 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashSet};
+
+use bevy::prelude::*;
+use bevy::render::camera::CameraProjection;
+use bevy::render::primitives::{Aabb, Frustum};
+
+use crate::world::coords::{ChunkPos, FloatPos};
+
+/// Chunks outside the frustum are treated as if they were this many times further away than they are, so chunks the
+/// player is looking at are loaded first, but chunks right behind them still come before ones in the far distance.
+const BEHIND_PENALTY: f32 = 4.0;
+
+/// Priorities are only recalculated when the camera turns more than this (cosine of ~10 degrees)
+const TURN_THRESHOLD: f32 = 0.985;
+
+/// Where chunks are being loaded for
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct LoadView {
+    /// Position of the camera, in world units
+    pub(crate) position: Vec3,
+    pub(crate) forward: Vec3,
+    pub(crate) frustum: Frustum
+}
+
+impl LoadView {
+    /// View from a camera with `transform` and `projection`, calculating the frustum the same way bevy does
+    pub(crate) fn new(transform: &Transform, projection: &PerspectiveProjection) -> Self {
+        let view_projection = projection.get_projection_matrix() * transform.compute_matrix().inverse();
+        let frustum = Frustum::from_view_projection(&view_projection, &transform.translation, &transform.back(), projection.far());
+
+        Self {
+            position: transform.translation,
+            forward: transform.forward(),
+            frustum
+        }
+    }
+
+    /// Whether any part of the chunk at `pos` is inside the frustum
+    pub(crate) fn sees(&self, pos: ChunkPos) -> bool {
+        let min = pos.corner().0;
+        self.frustum.intersects_obb(&Aabb::from_min_max(min, min + ChunkPos::extent()), &Mat4::IDENTITY)
+    }
+
+    /// Priority of the chunk at `pos`, lower is more urgent
+    pub(crate) fn priority(&self, pos: ChunkPos) -> f32 {
+        let distance = pos.center().0.distance(self.position);
+
+        if self.sees(pos) {
+            distance
+        } else {
+            distance * BEHIND_PENALTY
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Request {
+    priority: f32,
+    pos: ChunkPos
+}
+
+impl PartialEq for Request {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Request {}
+
+impl PartialOrd for Request {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Request {
+    // Reversed, since BinaryHeap pops the largest item first and we want the lowest priority value first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.priority.partial_cmp(&self.priority).unwrap_or(Ordering::Equal)
+    }
+}
+
+/// Chunks waiting to be generated (or loaded) and meshed, ordered by how close they are to the player and whether
+/// the player is looking at them.
+#[derive(Default)]
+pub(crate) struct LoadQueue {
+    pending: HashSet<ChunkPos>,
+    heap: BinaryHeap<Request>,
+    view: Option<LoadView>
+}
+
+impl LoadQueue {
+    fn priority(&self, pos: ChunkPos) -> f32 {
+        match &self.view {
+            Some(view) => view.priority(pos),
+            None => 0.0
+        }
+    }
+
+    /// Queue the chunk at `pos`, if it isn't already.
+    pub(crate) fn request(&mut self, pos: ChunkPos) {
+        if self.pending.insert(pos) {
+            self.heap.push(Request { priority: self.priority(pos), pos });
+        }
+    }
+
+    /// Drop every queued chunk that `keep` returns false for, e.g. because it's out of range now.
+    pub(crate) fn retain<F: FnMut(ChunkPos) -> bool>(&mut self, mut keep: F) {
+        self.pending.retain(|&pos| keep(pos));
+
+        let pending = &self.pending;
+        self.heap = std::mem::take(&mut self.heap).into_iter()
+            .filter(|request| pending.contains(&request.pos))
+            .collect();
+    }
+
+    /// The most urgent chunk, removing it from the queue
+    pub(crate) fn pop(&mut self) -> Option<ChunkPos> {
+        let request = self.heap.pop()?;
+        self.pending.remove(&request.pos);
+        Some(request.pos)
+    }
+
+    /// Update where the player is and where they're looking. Priorities are only recalculated when the player moved
+    /// into another chunk or turned a noticeable amount since the last time, since that means going over every queued
+    /// chunk.
+    pub(crate) fn set_view(&mut self, view: LoadView) {
+        let changed = match &self.view {
+            Some(old) => {
+                FloatPos(old.position).chunk() != FloatPos(view.position).chunk()
+                    || old.forward.dot(view.forward) < TURN_THRESHOLD
+            },
+            None => true
+        };
+
+        if !changed {
+            return
+        }
+
+        self.view = Some(view);
+        self.heap = self.pending.iter()
+            .map(|&pos| Request { priority: view.priority(pos), pos })
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn view(position: Vec3, looking_at: Vec3) -> LoadView {
+        LoadView::new(&Transform::from_translation(position).looking_at(looking_at, Vec3::Y), &Default::default())
+    }
+
+    /// Middle of chunk (0, 0, 0)
+    fn middle() -> Vec3 {
+        ChunkPos::new(0, 0, 0).center().0
+    }
+
+    fn drain(queue: &mut LoadQueue) -> Vec<ChunkPos> {
+        std::iter::from_fn(|| queue.pop()).collect()
+    }
+
+    #[test]
+    fn frustum() {
+        let view = view(middle(), middle() + Vec3::Z);
+        assert!(view.sees(ChunkPos::new(0, 0, 0)));
+        assert!(view.sees(ChunkPos::new(0, 0, 5)));
+        assert!(!view.sees(ChunkPos::new(0, 0, -5)));
+        assert!(!view.sees(ChunkPos::new(0, 10, 1)));
+    }
+
+    #[test]
+    fn priorities() {
+        let mut queue = LoadQueue::default();
+        queue.set_view(view(middle(), middle() + Vec3::Z));
+
+        for z in -3..=3 {
+            queue.request(ChunkPos::new(0, 0, z));
+        }
+        queue.request(ChunkPos::new(0, 0, 1));
+        assert_eq!(queue.pending.len(), 7);
+
+        // Closest first, but the chunks in front of the player come before those the same distance behind them,
+        // and chunks right behind the player come before ones far ahead.
+        let z: Vec<_> = drain(&mut queue).iter().map(|pos| pos.0.z).collect();
+        assert_eq!(z, vec![0, 1, 2, 3, -1, -2, -3]);
+        assert!(queue.pending.is_empty());
+    }
+
+    #[test]
+    fn turning_around() {
+        let mut queue = LoadQueue::default();
+        queue.set_view(view(middle(), middle() + Vec3::Z));
+
+        for z in [-3, 3] {
+            queue.request(ChunkPos::new(0, 0, z));
+        }
+
+        // Small movements within the same chunk don't change anything
+        queue.set_view(view(middle() + Vec3::X, middle() + Vec3::X + Vec3::Z));
+        assert_eq!(queue.heap.peek().unwrap().pos, ChunkPos::new(0, 0, 3));
+
+        queue.set_view(view(middle(), middle() - Vec3::Z));
+        assert_eq!(drain(&mut queue), vec![ChunkPos::new(0, 0, -3), ChunkPos::new(0, 0, 3)]);
+    }
+
+    #[test]
+    fn retain() {
+        let mut queue = LoadQueue::default();
+        for x in 0..10 {
+            queue.request(ChunkPos::new(x, 0, 0));
+        }
+
+        queue.retain(|pos| pos.0.x % 2 == 0);
+        let left = drain(&mut queue);
+        assert_eq!(left.len(), 5);
+        assert!(left.iter().all(|pos| pos.0.x % 2 == 0));
+    }
+}
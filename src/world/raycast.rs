@@ -0,0 +1,222 @@
+use bevy::prelude::*;
+
+use crate::world::chunk::voxel_scale;
+use crate::world::coords::{Direction, FloatPos, WorldPos};
+use crate::world::manager::ChunkManager;
+use crate::world::voxel::Voxel;
+
+/// A voxel that a ray hit
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct RaycastHit {
+    pub(crate) pos: WorldPos,
+    /// Normal of the face the ray went into the voxel through, or `None` if the ray started inside the voxel
+    pub(crate) normal: Option<Direction>,
+    /// How far along the ray the voxel was hit, in world units
+    pub(crate) distance: f32,
+    pub(crate) voxel: Voxel
+}
+
+impl RaycastHit {
+    /// The voxel in front of the face that was hit, e.g. where a voxel placed against it would go
+    pub(crate) fn adjacent(&self) -> Option<WorldPos> {
+        Some(self.pos + self.normal?.offset())
+    }
+}
+
+impl ChunkManager {
+    /// Find the first voxel that `hits` returns true for along the ray from `origin` in `direction`, up to
+    /// `max_distance` world units away. Voxels in chunks that aren't loaded are skipped, so the distance has to be finite
+    /// or the ray would never stop.
+    ///
+    /// This is the voxel traversal from Amanatides & Woo's "A Fast Voxel Traversal Algorithm for Ray Tracing", which
+    /// steps through every voxel the ray touches, in order, by always crossing the closest voxel boundary next.
+    pub(crate) fn raycast<F: FnMut(&Voxel) -> bool>(
+        &self,
+        origin: FloatPos,
+        direction: Vec3,
+        max_distance: f32,
+        mut hits: F
+    ) -> Option<RaycastHit> {
+        let direction = direction.try_normalize()?;
+        if !max_distance.is_finite() {
+            return None
+        }
+
+        // Everything below is in voxels rather than world units
+        let origin = (origin.0 / voxel_scale()).to_array();
+        let max_distance = max_distance / voxel_scale();
+        let dir = direction.to_array();
+
+        let mut pos = [origin[0].floor() as i32, origin[1].floor() as i32, origin[2].floor() as i32];
+        let mut step = [0; 3];
+        // Distance along the ray to the next voxel boundary on each axis
+        let mut next = [f32::INFINITY; 3];
+        // Distance along the ray between voxel boundaries on each axis
+        let mut delta = [f32::INFINITY; 3];
+
+        for axis in 0..3 {
+            if dir[axis] > 0.0 {
+                step[axis] = 1;
+                next[axis] = (pos[axis] as f32 + 1.0 - origin[axis]) / dir[axis];
+            } else if dir[axis] < 0.0 {
+                step[axis] = -1;
+                next[axis] = (pos[axis] as f32 - origin[axis]) / dir[axis];
+            }
+
+            if dir[axis] != 0.0 {
+                delta[axis] = 1.0 / dir[axis].abs();
+            }
+        }
+
+        let mut distance = 0.0;
+        let mut normal = None;
+
+        loop {
+            let world_pos = WorldPos(IVec3::from(pos));
+            if let Some(voxel) = self.voxel(world_pos) {
+                if hits(voxel) {
+                    return Some(RaycastHit { pos: world_pos, normal, distance: distance * voxel_scale(), voxel: *voxel });
+                }
+            }
+
+            let axis = if next[0] < next[1] && next[0] < next[2] {
+                0
+            } else if next[1] < next[2] {
+                1
+            } else {
+                2
+            };
+
+            if next[axis] > max_distance {
+                return None
+            }
+
+            distance = next[axis];
+            pos[axis] += step[axis];
+            next[axis] += delta[axis];
+
+            // Direction::ALL goes +X, -X, +Y, -Y, +Z, -Z, and the face we went in through faces against the step
+            normal = Some(Direction::ALL[axis * 2 + (step[axis] > 0) as usize]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{Axis, Volume};
+    use crate::world::chunk::{Chunk, CHUNK_SIZE};
+    use crate::world::coords::ChunkPos;
+
+    const SIZE: i32 = CHUNK_SIZE as i32;
+
+    /// Empty chunks around the origin, on both sides of every axis
+    fn manager() -> ChunkManager {
+        let mut manager = ChunkManager::default();
+        for x in -1..=0 {
+            for y in -1..=0 {
+                for z in -1..=0 {
+                    manager.insert(Chunk::new(ChunkPos::new(x, y, z), Volume::filled(Voxel::inactive())));
+                }
+            }
+        }
+        manager
+    }
+
+    /// Position in voxels, converted to world units
+    fn at(x: f32, y: f32, z: f32) -> FloatPos {
+        FloatPos(Vec3::new(x, y, z) * voxel_scale())
+    }
+
+    fn solid(voxel: &Voxel) -> bool {
+        voxel.active
+    }
+
+    #[test]
+    fn axis_aligned() {
+        let mut manager = manager();
+        manager.set_voxel(WorldPos::new(5, 0, 0), Voxel::active());
+        manager.set_voxel(WorldPos::new(0, -4, 0), Voxel::active());
+
+        let hit = manager.raycast(at(0.5, 0.5, 0.5), Vec3::X, 100.0, solid).unwrap();
+        assert_eq!(hit.pos, WorldPos::new(5, 0, 0));
+        assert_eq!(hit.normal, Some(Direction::WEST));
+        assert!((hit.distance - 4.5 * voxel_scale()).abs() < 1e-4);
+        assert_eq!(hit.voxel, Voxel::active());
+        assert_eq!(hit.adjacent(), Some(WorldPos::new(4, 0, 0)));
+
+        // Down, across the chunk boundary at y = 0
+        let hit = manager.raycast(at(0.5, 0.5, 0.5), -Vec3::Y, 100.0, solid).unwrap();
+        assert_eq!(hit.pos, WorldPos::new(0, -4, 0));
+        assert_eq!(hit.normal, Some(Direction::UP));
+        assert!((hit.distance - 3.5 * voxel_scale()).abs() < 1e-4);
+
+        // Nothing that way
+        assert_eq!(manager.raycast(at(0.5, 0.5, 0.5), Vec3::Z, 100.0, solid), None);
+        assert_eq!(manager.raycast(at(0.5, 0.5, 0.5), Vec3::ZERO, 100.0, solid), None);
+
+        // Rays that never end are refused, even if they would hit something
+        assert_eq!(manager.raycast(at(0.5, 0.5, 0.5), Vec3::Z, f32::INFINITY, solid), None);
+        assert_eq!(manager.raycast(at(0.5, 0.5, 0.5), Vec3::X, f32::NAN, solid), None);
+    }
+
+    #[test]
+    fn negative_coordinates() {
+        let mut manager = manager();
+        let target = WorldPos::new(-SIZE + 1, -3, -2);
+        manager.set_voxel(target, Voxel::active());
+
+        // From the positive side of the chunk boundary at x = 0, along -X
+        let hit = manager.raycast(at(2.5, -2.5, -1.5), -Vec3::X, 100.0, solid).unwrap();
+        assert_eq!(hit.pos, target);
+        assert_eq!(hit.normal, Some(Direction::EAST));
+        assert!((hit.distance - (SIZE as f32 + 0.5) * voxel_scale()).abs() < 1e-3);
+
+        // Too short to reach it
+        assert_eq!(manager.raycast(at(2.5, -2.5, -1.5), -Vec3::X, SIZE as f32 * voxel_scale(), solid), None);
+    }
+
+    #[test]
+    fn diagonal() {
+        let mut manager = manager();
+        let target = WorldPos::new(-3, -3, -3);
+        manager.set_voxel(target, Voxel::active());
+
+        // Straight through the corners of the voxels, from (2, 2, 2) to (-3, -3, -3)
+        let origin = at(2.5, 2.5, 2.5);
+        let hit = manager.raycast(origin, -Vec3::ONE, 100.0, solid).unwrap();
+        assert_eq!(hit.pos, target);
+        assert!(hit.normal.is_some());
+        let expected = (origin.0 - target.corner().0 - Vec3::splat(voxel_scale())).length();
+        assert!((hit.distance - expected).abs() < 1e-3);
+
+        // A shallow diagonal hits the voxels in the order it passes through them
+        manager.set_voxel(WorldPos::new(3, 0, 1), Voxel::active());
+        let hit = manager.raycast(at(0.5, 0.5, 0.5), Vec3::new(4.0, 0.0, 1.0), 100.0, solid).unwrap();
+        assert_eq!(hit.pos, WorldPos::new(3, 0, 1));
+        assert_eq!(hit.normal, Some(Direction::WEST));
+
+        // Starting further back along Z, the ray stays at z = 0 for longer and goes into (4, 0, 1) from the front
+        manager.set_voxel(WorldPos::new(3, 0, 1), Voxel::inactive());
+        manager.set_voxel(WorldPos::new(4, 0, 1), Voxel::active());
+        let hit = manager.raycast(at(0.5, 0.5, 0.1), Vec3::new(4.0, 0.0, 1.0), 100.0, solid).unwrap();
+        assert_eq!(hit.pos, WorldPos::new(4, 0, 1));
+        assert_eq!(hit.normal, Some(Direction::NORTH));
+    }
+
+    #[test]
+    fn predicate() {
+        let mut manager = manager();
+        manager.set_voxel(WorldPos::new(0, 0, 2), Voxel::active());
+        manager.set_voxel(WorldPos::new(0, 0, 4), Voxel::active().with_axis(Axis::X));
+
+        let hit = manager.raycast(at(0.5, 0.5, 0.5), Vec3::Z, 100.0, |voxel| voxel.active && voxel.axis == Axis::X);
+        assert_eq!(hit.unwrap().pos, WorldPos::new(0, 0, 4));
+
+        // Starting inside a voxel that counts as a hit
+        let hit = manager.raycast(at(0.5, 0.5, 2.5), Vec3::Z, 100.0, solid).unwrap();
+        assert_eq!(hit.pos, WorldPos::new(0, 0, 2));
+        assert_eq!(hit.normal, None);
+        assert_eq!(hit.distance, 0.0);
+    }
+}
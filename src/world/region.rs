@@ -0,0 +1,503 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use bevy::prelude::*;
+
+use crate::world::chunk::Chunk;
+use crate::world::coords::ChunkPos;
+use crate::world::codec::{DecodeError, Reader};
+
+// Region file format, all numbers are little endian:
+//   magic        4 bytes, "svrg"
+//   version      u16
+//   region size  u16, has to match REGION_SIZE
+//   offset table REGION_VOLUME * (u32 offset, u32 length), offsets are from the start of the file and a length of 0
+//                means the chunk isn't in the region
+//   chunk data   encoded chunks (see `Chunk::encode`), wherever the offset table says they are
+
+const MAGIC: [u8; 4] = *b"svrg";
+const REGION_VERSION: u16 = 1;
+
+/// Regions are cubes of chunks, this many chunks large on every side
+pub(crate) const REGION_SIZE: i32 = 16;
+const REGION_VOLUME: usize = (REGION_SIZE * REGION_SIZE * REGION_SIZE) as usize;
+const HEADER_LEN: usize = MAGIC.len() + 2 + 2 + REGION_VOLUME * 8;
+
+pub(crate) type RegionPosition = IVec3;
+
+/// The region containing the chunk at `pos`, and the index of the chunk within that region.
+pub(crate) fn chunk_to_region(pos: ChunkPos) -> (RegionPosition, usize) {
+    let pos = pos.0;
+    let region = IVec3::new(
+        pos.x.div_euclid(REGION_SIZE),
+        pos.y.div_euclid(REGION_SIZE),
+        pos.z.div_euclid(REGION_SIZE)
+    );
+    let local = pos - region * REGION_SIZE;
+    let idx = local.x + local.y * REGION_SIZE + local.z * REGION_SIZE * REGION_SIZE;
+
+    (region, idx as usize)
+}
+
+/// Reasons that chunks couldn't be loaded from or saved to disk
+#[derive(Debug)]
+pub(crate) enum StorageError {
+    Io(io::Error),
+    /// A region file was damaged or isn't a region file at all
+    CorruptRegion { path: PathBuf, reason: &'static str },
+    /// A chunk in a region file couldn't be decoded
+    CorruptChunk { pos: ChunkPos, error: DecodeError }
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "{}", error),
+            Self::CorruptRegion { path, reason } => write!(f, "region file {} is corrupt: {}", path.display(), reason),
+            Self::CorruptChunk { pos, error } => write!(f, "chunk at {} is corrupt: {}", pos, error)
+        }
+    }
+}
+
+impl Error for StorageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            Self::CorruptChunk { error, .. } => Some(error),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for StorageError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+/// Some chunks couldn't be saved, because something went wrong with the region files they're in. Every other chunk
+/// was still saved.
+#[derive(Debug)]
+pub(crate) struct SaveError {
+    /// Chunks that weren't saved
+    pub(crate) chunks: Vec<ChunkPos>,
+    /// What went wrong with each region that couldn't be saved
+    pub(crate) regions: Vec<(RegionPosition, StorageError)>
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} chunks weren't saved", self.chunks.len())?;
+        for (pos, error) in &self.regions {
+            write!(f, ", region {} {} {}: {}", pos.x, pos.y, pos.z, error)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for SaveError {}
+
+/// The encoded chunks of a single region
+#[derive(Clone)]
+struct Region {
+    chunks: Vec<Option<Vec<u8>>>
+}
+
+impl Region {
+    fn empty() -> Self {
+        Self { chunks: vec![None; REGION_VOLUME] }
+    }
+
+    fn read(bytes: &[u8], path: &Path) -> Result<Self, StorageError> {
+        let corrupt = |reason| StorageError::CorruptRegion { path: path.to_owned(), reason };
+        let mut reader = Reader::new(bytes);
+
+        if reader.bytes(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err(corrupt("not a region file"));
+        }
+        if reader.u16().map_err(|_| corrupt("truncated header"))? != REGION_VERSION {
+            return Err(corrupt("unsupported region version"));
+        }
+        if reader.u16().map_err(|_| corrupt("truncated header"))? != REGION_SIZE as u16 {
+            return Err(corrupt("region size doesn't match"));
+        }
+
+        let table = reader.bytes(REGION_VOLUME * 8).map_err(|_| corrupt("truncated offset table"))?;
+        let mut region = Self::empty();
+
+        for (idx, entry) in table.chunks_exact(8).enumerate() {
+            let offset = u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]) as usize;
+            let len = u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]) as usize;
+            if len == 0 {
+                continue;
+            }
+
+            let data = offset.checked_add(len)
+                .and_then(|end| bytes.get(offset..end))
+                .ok_or_else(|| corrupt("chunk data is out of bounds"))?;
+            region.chunks[idx] = Some(data.to_vec());
+        }
+
+        Ok(region)
+    }
+
+    fn write(&self) -> Vec<u8> {
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        let mut data = Vec::new();
+
+        header.extend_from_slice(&MAGIC);
+        header.extend_from_slice(&REGION_VERSION.to_le_bytes());
+        header.extend_from_slice(&(REGION_SIZE as u16).to_le_bytes());
+
+        for chunk in &self.chunks {
+            let (offset, len) = match chunk {
+                Some(bytes) => {
+                    let offset = HEADER_LEN + data.len();
+                    data.extend_from_slice(bytes);
+                    (offset as u32, bytes.len() as u32)
+                },
+                None => (0, 0)
+            };
+
+            header.extend_from_slice(&offset.to_le_bytes());
+            header.extend_from_slice(&len.to_le_bytes());
+        }
+
+        header.append(&mut data);
+        header
+    }
+}
+
+/// A directory of region files that chunks can be saved to and loaded from.
+///
+/// Storage can be shared between threads, so chunks can be saved in the background while others are loaded. Saving
+/// only locks the region cache for as long as it takes to swap in the updated regions, so loads don't have to wait for
+/// regions to be encoded and written.
+pub(crate) struct WorldStorage {
+    dir: PathBuf,
+    // Regions are loaded lazily and kept around, so we don't have to read the file again for every chunk in it
+    regions: Mutex<HashMap<RegionPosition, Arc<Region>>>,
+    // Held for the whole of a save, so two saves can't write the same region at the same time
+    saving: Mutex<()>
+}
+
+impl WorldStorage {
+    /// Use the region files in `dir`, creating the directory if it doesn't exist yet.
+    pub(crate) fn open<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        fs::create_dir_all(dir.as_ref())?;
+
+        Ok(Self {
+            dir: dir.as_ref().to_owned(),
+            regions: Mutex::new(HashMap::new()),
+            saving: Mutex::new(())
+        })
+    }
+
+    pub(crate) fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn region_path(&self, pos: RegionPosition) -> PathBuf {
+        self.dir.join(format!("r.{}.{}.{}.svr", pos.x, pos.y, pos.z))
+    }
+
+    /// Read the region at `pos` from its file, or an empty region if it doesn't have a file yet
+    fn read_region(&self, pos: RegionPosition) -> Result<Region, StorageError> {
+        let path = self.region_path(pos);
+        match fs::read(&path) {
+            Ok(bytes) => Region::read(&bytes, &path),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Region::empty()),
+            Err(error) => Err(error.into())
+        }
+    }
+
+    fn region(&self, pos: RegionPosition) -> Result<Arc<Region>, StorageError> {
+        // Reading the file with the cache locked means a save can't swap in a newer region in the meantime, which we'd
+        // then overwrite with the old one
+        let mut regions = self.regions.lock().unwrap();
+        if let Some(region) = regions.get(&pos) {
+            return Ok(region.clone());
+        }
+
+        let region = Arc::new(self.read_region(pos)?);
+        regions.insert(pos, region.clone());
+        Ok(region)
+    }
+
+    /// Load the chunk at `pos`, if it's been saved before.
+    pub(crate) fn load_chunk(&self, pos: ChunkPos) -> Result<Option<Chunk>, StorageError> {
+        let (region_pos, idx) = chunk_to_region(pos);
+        let region = self.region(region_pos)?;
+        let bytes = match &region.chunks[idx] {
+            Some(bytes) => bytes,
+            None => return Ok(None)
+        };
+
+        let chunk = Chunk::decode(bytes).map_err(|error| StorageError::CorruptChunk { pos, error })?;
+        if chunk.position() != pos {
+            return Err(StorageError::CorruptChunk { pos, error: DecodeError::WrongPosition(chunk.position()) });
+        }
+
+        Ok(Some(chunk))
+    }
+
+    /// Only keep the cached regions that `keep` returns true for. Regions that are dropped are read from their file
+    /// again when they're needed.
+    pub(crate) fn retain_regions<F: FnMut(RegionPosition) -> bool>(&self, mut keep: F) {
+        self.regions.lock().unwrap().retain(|&pos, _| keep(pos));
+    }
+
+    #[cfg(test)]
+    pub(crate) fn cached_regions(&self) -> usize {
+        self.regions.lock().unwrap().len()
+    }
+
+    /// Save `chunks`, rewriting every region file that they're in. Returns how many region files were written.
+    /// A region that can't be read or written doesn't stop the chunks in other regions from being saved.
+    pub(crate) fn save_chunks<'a, I: IntoIterator<Item = &'a Chunk>>(&self, chunks: I) -> Result<usize, SaveError> {
+        let _saving = self.saving.lock().unwrap();
+
+        let mut regions: Vec<(RegionPosition, Vec<&Chunk>)> = Vec::new();
+        for chunk in chunks {
+            let (region_pos, _) = chunk_to_region(chunk.position());
+            match regions.iter_mut().find(|(pos, _)| *pos == region_pos) {
+                Some((_, chunks)) => chunks.push(chunk),
+                None => regions.push((region_pos, vec![chunk]))
+            }
+        }
+
+        let mut written = 0;
+        let mut failed = SaveError { chunks: Vec::new(), regions: Vec::new() };
+        for (region_pos, chunks) in regions {
+            match self.save_region(region_pos, &chunks) {
+                Ok(()) => written += 1,
+                Err(error) => {
+                    failed.chunks.extend(chunks.iter().map(|chunk| chunk.position()));
+                    failed.regions.push((region_pos, error));
+                }
+            }
+        }
+
+        if failed.regions.is_empty() {
+            Ok(written)
+        } else {
+            Err(failed)
+        }
+    }
+
+    /// Put `chunks` into the region at `pos` and write it to disk.
+    ///
+    /// Region files are written to a temporary file first which then replaces the old file, so if we crash halfway
+    /// through, the old version of the region is still intact. The cached region is only changed once that worked.
+    fn save_region(&self, pos: RegionPosition, chunks: &[&Chunk]) -> Result<(), StorageError> {
+        // Only saves write region files, so the file can't change while we're working on a copy of it
+        let cached = self.regions.lock().unwrap().get(&pos).cloned();
+        let mut region = match cached {
+            Some(cached) => Region::clone(&cached),
+            None => self.read_region(pos)?
+        };
+        for chunk in chunks {
+            let (_, idx) = chunk_to_region(chunk.position());
+            region.chunks[idx] = Some(chunk.encode());
+        }
+
+        let path = self.region_path(pos);
+        let tmp_path = path.with_extension("svr.tmp");
+
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(&region.write())?;
+        file.sync_all()?;
+        drop(file);
+
+        fs::rename(&tmp_path, &path)?;
+        if let Some(cached) = self.regions.lock().unwrap().get_mut(&pos) {
+            *cached = Arc::new(region);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{CubicVolume, Volume};
+    use crate::world::chunk::CHUNK_SIZE;
+    use crate::util::testing::test_dir;
+    use crate::world::coords::WorldPos;
+    use crate::world::voxel::Voxel;
+
+    fn test_chunk(pos: ChunkPos) -> Chunk {
+        let mut volume: CubicVolume<Voxel, CHUNK_SIZE> = Volume::filled(Voxel::inactive());
+        volume[(pos.0.x.rem_euclid(8) as usize, 1, 2)] = Voxel::active();
+        Chunk::new(pos, volume)
+    }
+
+    #[test]
+    fn region_positions() {
+        assert_eq!(chunk_to_region(ChunkPos::new(0, 0, 0)), (IVec3::ZERO, 0));
+        assert_eq!(chunk_to_region(ChunkPos::new(15, 0, 0)), (IVec3::ZERO, 15));
+        assert_eq!(chunk_to_region(ChunkPos::new(16, 1, 0)), (IVec3::new(1, 0, 0), 16));
+        assert_eq!(chunk_to_region(ChunkPos::new(-1, 0, 0)), (IVec3::new(-1, 0, 0), 15));
+        assert_eq!(chunk_to_region(ChunkPos::new(0, 0, -16)), (IVec3::new(0, 0, -1), 0));
+        assert_eq!(chunk_to_region(ChunkPos::new(0, 0, -17)), (IVec3::new(0, 0, -2), 15 * 256));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let dir = test_dir("save-and-load");
+        let chunks: Vec<_> = [ChunkPos::new(0, 0, 0), ChunkPos::new(-1, 3, 5), ChunkPos::new(40, -2, 0)]
+            .into_iter()
+            .map(test_chunk)
+            .collect();
+
+        let storage = WorldStorage::open(&dir).unwrap();
+        assert_eq!(storage.save_chunks(&chunks).unwrap(), 3);
+
+        // No temporary files are left over
+        assert!(fs::read_dir(&dir).unwrap().all(|entry| entry.unwrap().path().extension().unwrap() == "svr"));
+
+        // Load everything back from disk, with a fresh storage so nothing is cached
+        let storage = WorldStorage::open(&dir).unwrap();
+        for chunk in &chunks {
+            let loaded = storage.load_chunk(chunk.position()).unwrap().unwrap();
+            assert!(loaded.volume() == chunk.volume());
+        }
+        assert!(storage.load_chunk(ChunkPos::new(1, 0, 0)).unwrap().is_none());
+
+        // Saving another chunk in the same region keeps the chunks that were already there
+        storage.save_chunks([&test_chunk(ChunkPos::new(1, 0, 0))]).unwrap();
+        let storage = WorldStorage::open(&dir).unwrap();
+        assert!(storage.load_chunk(ChunkPos::new(0, 0, 0)).unwrap().is_some());
+        assert!(storage.load_chunk(ChunkPos::new(1, 0, 0)).unwrap().is_some());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn loading_while_saving() {
+        let dir = test_dir("loading-while-saving");
+        let storage = WorldStorage::open(&dir).unwrap();
+        storage.save_chunks([&test_chunk(ChunkPos::new(0, 0, 0))]).unwrap();
+
+        // Loads don't wait for a save that's still writing region files
+        let _saving = storage.saving.lock().unwrap();
+        assert!(storage.load_chunk(ChunkPos::new(0, 0, 0)).unwrap().is_some());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn manager_saves_changed_chunks() {
+        use crate::world::manager::ChunkManager;
+
+        let dir = test_dir("manager");
+        let mut manager = ChunkManager::with_storage(WorldStorage::open(&dir).unwrap());
+
+        // Generated chunks aren't saved unless they change
+        manager.load_or_generate(ChunkPos::new(0, 0, 0));
+        manager.load_or_generate(ChunkPos::new(20, 0, 0));
+        assert!(manager.take_dirty().is_empty());
+
+        manager.set_voxel(WorldPos::new(3, 4, 5), Voxel::active().with_axis(crate::util::Axis::X));
+        let dirty = manager.take_dirty();
+        assert_eq!(dirty.iter().map(|chunk| chunk.position()).collect::<Vec<_>>(), vec![ChunkPos::new(0, 0, 0)]);
+        assert_eq!(manager.storage().unwrap().save_chunks(&dirty).unwrap(), 1);
+        manager.finish_saving(&[ChunkPos::new(0, 0, 0)], &[]);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        // Taking the changed chunks clears them, and failed saves put them back
+        manager.set_voxel(WorldPos::new(3, 4, 5), Voxel::inactive());
+        assert_eq!(manager.take_dirty().len(), 1);
+        assert!(manager.take_dirty().is_empty());
+        manager.finish_saving(&[ChunkPos::new(0, 0, 0)], &[ChunkPos::new(0, 0, 0), ChunkPos::new(-100, 0, 0)]);
+        let dirty = manager.take_dirty();
+        assert_eq!(dirty.iter().map(|chunk| chunk.position()).collect::<Vec<_>>(), vec![ChunkPos::new(0, 0, 0)]);
+        manager.storage().unwrap().save_chunks(&dirty).unwrap();
+
+        // A new manager finds the changed chunk on disk instead of generating it
+        let mut manager = ChunkManager::with_storage(WorldStorage::open(&dir).unwrap());
+        let chunk = manager.load_or_generate(ChunkPos::new(0, 0, 0));
+        assert_eq!(chunk.volume()[(3, 4, 5)], Voxel::inactive());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_regions() {
+        let dir = test_dir("corrupt-regions");
+        let storage = WorldStorage::open(&dir).unwrap();
+        storage.save_chunks([&test_chunk(ChunkPos::new(0, 0, 0))]).unwrap();
+
+        let path = dir.join("r.0.0.0.svr");
+        let bytes = fs::read(&path).unwrap();
+
+        // A region that got cut off
+        fs::write(&path, &bytes[..HEADER_LEN - 3]).unwrap();
+        let result = WorldStorage::open(&dir).unwrap().load_chunk(ChunkPos::new(0, 0, 0));
+        assert!(matches!(result, Err(StorageError::CorruptRegion { .. })));
+
+        // A region with a damaged chunk in it
+        let mut damaged = bytes.clone();
+        damaged[HEADER_LEN] = b'x';
+        fs::write(&path, &damaged).unwrap();
+        let result = WorldStorage::open(&dir).unwrap().load_chunk(ChunkPos::new(0, 0, 0));
+        assert!(matches!(result, Err(StorageError::CorruptChunk { .. })));
+
+        // Something that isn't a region at all
+        fs::write(&path, b"hello").unwrap();
+        let result = WorldStorage::open(&dir).unwrap().load_chunk(ChunkPos::new(0, 0, 0));
+        assert!(matches!(result, Err(StorageError::CorruptRegion { .. })));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn saving_around_corrupt_regions() {
+        let dir = test_dir("saving-around-corrupt-regions");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("r.0.0.0.svr"), b"hello").unwrap();
+
+        // The chunk in the broken region isn't saved, but the one next to it still is
+        let storage = WorldStorage::open(&dir).unwrap();
+        let chunks = [test_chunk(ChunkPos::new(0, 0, 0)), test_chunk(ChunkPos::new(REGION_SIZE, 0, 0))];
+        let error = storage.save_chunks(&chunks).unwrap_err();
+        assert_eq!(error.chunks, vec![ChunkPos::new(0, 0, 0)]);
+        assert!(matches!(error.regions[..], [(pos, StorageError::CorruptRegion { .. })] if pos == IVec3::ZERO));
+
+        let storage = WorldStorage::open(&dir).unwrap();
+        assert!(storage.load_chunk(ChunkPos::new(REGION_SIZE, 0, 0)).unwrap().is_some());
+        // The broken region is left alone, in case it can be fixed by hand
+        assert_eq!(fs::read(dir.join("r.0.0.0.svr")).unwrap(), b"hello");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_saves_leave_the_cache_alone() {
+        let dir = test_dir("failed-saves");
+        let storage = WorldStorage::open(&dir).unwrap();
+        let old = test_chunk(ChunkPos::new(0, 0, 0));
+        storage.save_chunks([&old]).unwrap();
+        assert!(storage.load_chunk(ChunkPos::new(0, 0, 0)).unwrap().is_some());
+
+        // Put something in the way of the region file, so it can't be replaced
+        let path = dir.join("r.0.0.0.svr");
+        fs::remove_file(&path).unwrap();
+        fs::create_dir_all(path.join("in-the-way")).unwrap();
+
+        let mut volume = old.volume().clone();
+        volume[(7, 7, 7)] = Voxel::active();
+        assert!(storage.save_chunks([&Chunk::new(ChunkPos::new(0, 0, 0), volume)]).is_err());
+
+        // The region is still cached, and still has the chunk as it was on disk
+        let loaded = storage.load_chunk(ChunkPos::new(0, 0, 0)).unwrap().unwrap();
+        assert!(loaded.volume() == old.volume());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::{HashSet, VecDeque};
+
+use bevy::prelude::*;
+
+use crate::world::chunk::{Chunk, CHUNK_SIZE};
+use crate::world::coords::{ChunkPos, Direction, FloatPos};
+use crate::world::manager::ChunkManager;
+use crate::world::queue::LoadView;
+
+/// Which faces of a chunk are connected to each other through open space, i.e. whether you could see one face from
+/// the other by looking through the chunk. Faces are numbered like `Direction::ALL`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct FaceConnectivity([u8; 6]);
+
+impl FaceConnectivity {
+    /// Every face can see every other face
+    pub(crate) const ALL: Self = Self([0b111111; 6]);
+    /// No face can see any other face
+    pub(crate) const NONE: Self = Self([0; 6]);
+
+    pub(crate) fn of(chunk: &Chunk) -> Self {
+        if chunk.is_empty() {
+            return Self::ALL
+        }
+
+        let last = CHUNK_SIZE - 1;
+        let mut out = Self::NONE;
+
+        // Every face that a pocket of air touches can see every other face it touches
+        for region in chunk.volume().label_regions(|voxel| !voxel.active).regions {
+            let faces = (region.max.0 == last) as u8
+                | ((region.min.0 == 0) as u8) << 1
+                | ((region.max.1 == last) as u8) << 2
+                | ((region.min.1 == 0) as u8) << 3
+                | ((region.max.2 == last) as u8) << 4
+                | ((region.min.2 == 0) as u8) << 5;
+
+            for face in 0..6 {
+                if faces & (1 << face) != 0 {
+                    out.0[face] |= faces;
+                }
+            }
+        }
+
+        out
+    }
+
+    pub(crate) fn connected(&self, a: usize, b: usize) -> bool {
+        self.0[a] & (1 << b) != 0
+    }
+}
+
+/// The face on the other side of `face`
+fn opposite(face: usize) -> usize {
+    face ^ 1
+}
+
+impl ChunkManager {
+    /// Figure out which loaded chunks can be seen from `view`, within `range` chunks along each axis, and store them
+    /// as the visible chunks.
+    ///
+    /// This does a breadth first search outwards from the camera's chunk. A chunk is only entered if it's in the
+    /// frustum, and we only leave a chunk through a face that is connected to the face we came in through, so chunks
+    /// hidden behind solid terrain aren't visible, even if they're in the frustum. The search never turns back in a
+    /// direction it already went in, since you can't see around corners like that.
+    pub(crate) fn update_visibility(&mut self, view: &LoadView, range: IVec3) -> &HashSet<ChunkPos> {
+        let start = FloatPos(view.position).chunk();
+
+        let mut visible = HashSet::new();
+        if self.get(start).is_some() {
+            visible.insert(start);
+        }
+
+        // Position, the face we came in through and a bitmask of the directions we've gone in
+        let mut visited = HashSet::from([start]);
+        let mut queue = VecDeque::from([(start, None, 0u8)]);
+
+        while let Some((pos, entered, directions)) = queue.pop_front() {
+            // Can't tell what's in the camera's chunk if it isn't loaded, so assume it's open
+            let connectivity = self.connectivity(pos).unwrap_or(FaceConnectivity::ALL);
+
+            for (face, direction) in Direction::ALL.into_iter().enumerate() {
+                if directions & (1 << opposite(face)) != 0 {
+                    continue;
+                }
+
+                if matches!(entered, Some(entered) if !connectivity.connected(entered, face)) {
+                    continue;
+                }
+
+                let next = pos + direction.offset();
+                if visited.contains(&next) || !next.within(start, range) || self.get(next).is_none() || !view.sees(next) {
+                    continue;
+                }
+
+                visited.insert(next);
+                visible.insert(next);
+                queue.push_back((next, Some(opposite(face)), directions | 1 << face));
+            }
+        }
+
+        self.set_visible_chunks(visible);
+        self.visible_chunks()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{CubicVolume, Volume};
+    use crate::world::coords::WorldPos;
+    use crate::world::voxel::Voxel;
+
+    fn solid() -> CubicVolume<Voxel, CHUNK_SIZE> {
+        Volume::filled(Voxel::active())
+    }
+
+    /// Solid chunk with a straight tunnel through it along the Z axis
+    fn tunnel() -> CubicVolume<Voxel, CHUNK_SIZE> {
+        let mut volume = solid();
+        for z in 0..CHUNK_SIZE {
+            volume[(10, 10, z)] = Voxel::inactive();
+        }
+        volume
+    }
+
+    fn view() -> LoadView {
+        // In the middle of chunk (0, 0, 0), looking towards +Z
+        let pos = Vec3::splat(CHUNK_SIZE as f32 / 2.0);
+        LoadView::new(&Transform::from_translation(pos).looking_at(pos + Vec3::Z, Vec3::Y), &Default::default())
+    }
+
+    #[test]
+    fn connectivity() {
+        let empty = Chunk::new(ChunkPos::new(0, 0, 0), Volume::filled(Voxel::inactive()));
+        assert_eq!(FaceConnectivity::of(&empty), FaceConnectivity::ALL);
+        assert_eq!(FaceConnectivity::of(&Chunk::new(ChunkPos::new(0, 0, 0), solid())), FaceConnectivity::NONE);
+
+        let tunnel = FaceConnectivity::of(&Chunk::new(ChunkPos::new(0, 0, 0), tunnel()));
+        assert!(tunnel.connected(4, 5));
+        assert!(tunnel.connected(5, 4));
+        assert!(!tunnel.connected(0, 1));
+        assert!(!tunnel.connected(4, 0));
+
+        // A pocket of air that doesn't touch any face doesn't connect anything
+        let mut pocket = solid();
+        pocket[(5, 5, 5)] = Voxel::inactive();
+        assert_eq!(FaceConnectivity::of(&Chunk::new(ChunkPos::new(0, 0, 0), pocket)), FaceConnectivity::NONE);
+
+        // An L shaped tunnel connects the faces at its ends
+        let mut bend = solid();
+        for x in 0..=10 {
+            bend[(x, 10, 10)] = Voxel::inactive();
+        }
+        for y in 10..CHUNK_SIZE {
+            bend[(10, y, 10)] = Voxel::inactive();
+        }
+        let bend = FaceConnectivity::of(&Chunk::new(ChunkPos::new(0, 0, 0), bend));
+        assert!(bend.connected(1, 2));
+        assert!(!bend.connected(0, 2));
+    }
+
+    #[test]
+    fn cave_culling() {
+        let range = IVec3::splat(8);
+        let mut manager = ChunkManager::default();
+
+        // The camera's chunk, a row of chunks in front of it and one behind it
+        manager.insert(Chunk::new(ChunkPos::new(0, 0, 0), tunnel()));
+        manager.insert(Chunk::new(ChunkPos::new(0, 0, 1), tunnel()));
+        manager.insert(Chunk::new(ChunkPos::new(0, 0, 2), solid()));
+        manager.insert(Chunk::new(ChunkPos::new(0, 0, 3), tunnel()));
+        manager.insert(Chunk::new(ChunkPos::new(0, 0, -1), tunnel()));
+
+        // A chunk next to the tunnel, which can't be seen through the walls of the tunnel
+        manager.insert(Chunk::new(ChunkPos::new(1, 0, 1), tunnel()));
+
+        // We can see down the tunnel until the solid chunk, but not past it or behind us
+        let visible = manager.update_visibility(&view(), range).clone();
+        assert_eq!(visible, HashSet::from([ChunkPos::new(0, 0, 0), ChunkPos::new(0, 0, 1), ChunkPos::new(0, 0, 2)]));
+
+        // Digging through the solid chunk lets us see further
+        for z in 0..CHUNK_SIZE as i32 {
+            manager.set_voxel(WorldPos::new(10, 10, 2 * CHUNK_SIZE as i32 + z), Voxel::inactive());
+        }
+        assert!(manager.update_visibility(&view(), range).contains(&ChunkPos::new(0, 0, 3)));
+
+        // Out of range
+        assert!(!manager.update_visibility(&view(), IVec3::splat(2)).contains(&ChunkPos::new(0, 0, 3)));
+    }
+
+    #[test]
+    fn open_air() {
+        // With nothing in the way, everything in the frustum is visible
+        let mut manager = ChunkManager::default();
+        for x in -4..=4 {
+            for z in -4..=4 {
+                manager.insert(Chunk::new(ChunkPos::new(x, 0, z), Volume::filled(Voxel::inactive())));
+            }
+        }
+
+        let view = view();
+        let visible = manager.update_visibility(&view, IVec3::splat(8)).clone();
+        for x in -4..=4 {
+            for z in -4..=4 {
+                let pos = ChunkPos::new(x, 0, z);
+                assert_eq!(visible.contains(&pos), pos == ChunkPos::new(0, 0, 0) || view.sees(pos), "{}", pos);
+            }
+        }
+        assert!(!visible.contains(&ChunkPos::new(0, 0, -2)));
+    }
+}
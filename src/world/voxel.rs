@@ -1,16 +1,32 @@
+use serde::{Deserialize, Serialize};
+
+use crate::util::{Axis, Orientable, Orientation};
+
 // todo: different voxel types/themes, make as compact as possible, maybe a u8 where if no bits are
 //  set the voxel is inactive, and otherwise it indicates the theme ID
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub(crate) struct Voxel {
-    pub(crate) active: bool
+    pub(crate) active: bool,
+    /// Axis this voxel is aligned along, for voxels that have a direction (like logs or pillars).
+    pub(crate) axis: Axis
 }
 
 impl Voxel {
     pub(crate) const fn active() -> Self {
-        Self { active: true }
+        Self { active: true, axis: Axis::Y }
     }
     
     pub(crate) const fn inactive() -> Self {
-        Self { active: false }
+        Self { active: false, axis: Axis::Y }
+    }
+
+    pub(crate) const fn with_axis(self, axis: Axis) -> Self {
+        Self { axis, ..self }
     }
-}
\ No newline at end of file
+}
+
+impl Orientable for Voxel {
+    fn oriented(self, orientation: Orientation) -> Self {
+        self.with_axis(self.axis.oriented(orientation))
+    }
+}